@@ -0,0 +1,25 @@
+#!/bin/bash
+# Compares LD_SHOW_AUXV output of a bundled binary with the same binary run natively.
+# The entries that depend on ASLR or on the way the binary is loaded are compared by name only.
+set -e
+
+ARCH="${1:-$(uname -m)}"
+SHARUN="$PWD/sharun-$ARCH"
+TEST_DIR="$(mktemp -d)"
+trap 'rm -rf "$TEST_DIR"' EXIT
+
+BIN="$(type -P true)"
+SHARUN="$SHARUN" "$PWD/lib4bin" -w -d "$TEST_DIR" "$BIN" >/dev/null
+
+show_auxv() {
+    LD_SHOW_AUXV=1 "$@"|sed -E \
+        -e 's/^(AT_(SYSINFO_EHDR|PHDR|PHNUM|BASE|ENTRY|RANDOM|EXECFN)):.*/\1/' \
+        -e 's/[[:space:]]+/ /g'|sort
+}
+
+if ! diff -u <(show_auxv "$BIN") <(show_auxv "$TEST_DIR/bin/true")
+    then
+        echo "auxv of the bundled binary differs from the native one" >&2
+        exit 1
+fi
+echo "auxv matches"
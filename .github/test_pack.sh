@@ -0,0 +1,52 @@
+#!/bin/bash
+# Packs a bundle into a single file, runs it and extracts it back with --extract,
+# the extracted dir must match the original one. A damaged cache dir must be extracted
+# again and a damaged payload must be refused.
+set -e
+
+ARCH="${1:-$(uname -m)}"
+SHARUN="$PWD/sharun-$ARCH"
+TEST_DIR="$(mktemp -d)"
+trap 'rm -rf "$TEST_DIR"' EXIT
+export XDG_CACHE_HOME="$TEST_DIR/cache"
+
+SHARUN="$SHARUN" "$PWD/lib4bin" -w -g -d "$TEST_DIR/bundle" "$(type -P echo)" >/dev/null
+
+"$TEST_DIR/bundle/sharun" pack "$TEST_DIR/bundle" -o "$TEST_DIR/echo"
+[ "$("$TEST_DIR/echo" sharun)" = 'sharun' ]
+# the second run is from the cache
+[ "$("$TEST_DIR/echo" sharun)" = 'sharun' ]
+
+"$TEST_DIR/echo" --extract "$TEST_DIR/extracted"
+diff -r --no-dereference "$TEST_DIR/bundle" "$TEST_DIR/extracted"
+list_files() {
+    (cd "$1" && find . -printf '%p %y %m %l %n\n'|sort)
+}
+if ! diff -u <(list_files "$TEST_DIR/bundle") <(list_files "$TEST_DIR/extracted")
+    then
+        echo "modes, symlinks or hard links of the extracted bundle differ" >&2
+        exit 1
+fi
+
+# a damaged cache dir is extracted again
+rm -f "$XDG_CACHE_HOME"/sharun/packed/*/shared/bin/echo
+[ "$("$TEST_DIR/echo" sharun)" = 'sharun' ]
+
+# packing over the sharun that is copied keeps it intact
+cp -a "$TEST_DIR/bundle" "$TEST_DIR/self"
+"$TEST_DIR/self/sharun" pack "$TEST_DIR/self" -o "$TEST_DIR/self/sharun"
+[ "$("$TEST_DIR/self/bin/echo" sharun)" = 'sharun' ]
+[ "$("$TEST_DIR/self/sharun" echo sharun)" = 'sharun' ]
+
+# a byte in the middle of the payload
+mkdir "$TEST_DIR/damaged"
+cp "$TEST_DIR/echo" "$TEST_DIR/damaged/echo"
+SIZE="$(stat -c %s "$TEST_DIR/damaged/echo")"
+printf 'x'|dd of="$TEST_DIR/damaged/echo" bs=1 seek="$((SIZE - 1024))" conv=notrunc status=none
+rm -rf "$XDG_CACHE_HOME"
+if "$TEST_DIR/damaged/echo" sharun 2>/dev/null
+    then
+        echo "the damaged payload was not refused" >&2
+        exit 1
+fi
+echo "pack round-trip works"
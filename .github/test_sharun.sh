@@ -0,0 +1,18 @@
+#!/bin/sh
+set -e
+
+ARCH="${1:-$(uname -m)}"
+
+apk add bash file binutils patchelf findutils grep sed coreutils
+
+TEST_DIR="$(mktemp -d)"
+
+"$PWD/sharun-$ARCH" --version
+
+SHARUN="$PWD/sharun-$ARCH" \
+"$PWD/lib4bin" -w -g -d "$TEST_DIR" /bin/busybox "$(which bash)"
+
+[ "$("$TEST_DIR/bin/busybox" echo sharun)" = 'sharun' ]
+[ "$("$TEST_DIR/sharun" bash -c 'echo $BASH_VERSION')" = "$(bash -c 'echo $BASH_VERSION')" ]
+
+rm -rf "$TEST_DIR"
@@ -0,0 +1,162 @@
+// Loaded by sharun with 'ld.so --audit' (glibc only). The audit interface runs
+// in its own link namespace, so unlike the preload library it links libc.
+// Libraries that are dlopen()ed by an absolute host path, like
+// '/usr/lib/x86_64-linux-gnu/gtk-3.0/...' or '/usr/lib/dri/...', are looked up
+// in the bundle library directories from SHARUN_AUDIT_LIBRARY_PATH first.
+#![no_std]
+
+use core::{slice, cell::UnsafeCell, ffi::{c_char, c_int, c_uint}};
+
+
+const PATH_MAX: usize = 4096;
+const F_OK: c_int = 0;
+const STDERR: c_int = 2;
+
+const LA_SER_ORIG: c_uint = 0x01;
+const LA_SER_LIBPATH: c_uint = 0x02;
+const LA_SER_RUNPATH: c_uint = 0x04;
+const LA_SER_CONFIG: c_uint = 0x08;
+const LA_SER_DEFAULT: c_uint = 0x40;
+const LA_SER_SECURE: c_uint = 0x80;
+
+// the usual host library directories, the multiarch directory after them is skipped too
+const SYSTEM_LIB_DIRS: [&[u8]; 8] = [
+    b"/usr/local/lib64/", b"/usr/local/lib/", b"/usr/lib64/", b"/usr/lib32/",
+    b"/usr/lib/", b"/lib64/", b"/lib32/", b"/lib/"
+];
+
+#[link(name = "c")]
+extern "C" {
+    fn getenv(name: *const c_char) -> *mut c_char;
+    fn setenv(name: *const c_char, val: *const c_char, overwrite: c_int) -> c_int;
+    fn unsetenv(name: *const c_char) -> c_int;
+    fn access(path: *const c_char, mode: c_int) -> c_int;
+    fn write(fd: c_int, buf: *const u8, count: usize) -> isize;
+}
+
+struct Audit {
+    library_path: &'static [u8],
+    is_debug: bool,
+    buf: [u8; PATH_MAX]
+}
+
+// la_objsearch is called with the loader lock held, so one buffer is enough
+struct Global(UnsafeCell<Audit>);
+
+unsafe impl Sync for Global {}
+
+static AUDIT: Global = Global(UnsafeCell::new(Audit {
+    library_path: &[],
+    is_debug: false,
+    buf: [0; PATH_MAX]
+}));
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+unsafe fn c_str<'a>(str: *const c_char) -> &'a [u8] {
+    if str.is_null() {
+        return &[]
+    }
+    let mut len = 0;
+    while *str.add(len) != 0 {
+        len += 1
+    }
+    slice::from_raw_parts(str as *const u8, len)
+}
+
+fn log(parts: &[&[u8]]) {
+    for part in parts {
+        unsafe { write(STDERR, part.as_ptr(), part.len()) };
+    }
+}
+
+fn flag_name(flag: c_uint) -> &'static [u8] {
+    match flag {
+        LA_SER_ORIG => b"orig",
+        LA_SER_LIBPATH => b"libpath",
+        LA_SER_RUNPATH => b"runpath",
+        LA_SER_CONFIG => b"config",
+        LA_SER_DEFAULT => b"default",
+        LA_SER_SECURE => b"secure",
+        _ => b"unknown"
+    }
+}
+
+fn strip_system_dir(name: &[u8]) -> Option<&[u8]> {
+    let dir = SYSTEM_LIB_DIRS.iter().find(|dir| name.starts_with(dir))?;
+    let rest = &name[dir.len()..];
+    if let Some(slash) = rest.iter().position(|&byte| byte == b'/') {
+        if rest[..slash].windows(7).any(|window| window == b"-linux-") {
+            return Some(&rest[slash + 1..])
+        }
+    }
+    Some(rest)
+}
+
+unsafe fn find_in_bundle(audit: &mut Audit, name: &[u8]) -> Option<*mut c_char> {
+    let rest = strip_system_dir(name)?;
+    for dir in audit.library_path.split(|&byte| byte == b':') {
+        if dir.is_empty() || name.starts_with(dir) {
+            continue
+        }
+        let len = dir.len() + 1 + rest.len();
+        if len >= PATH_MAX {
+            continue
+        }
+        audit.buf[..dir.len()].copy_from_slice(dir);
+        audit.buf[dir.len()] = b'/';
+        audit.buf[dir.len() + 1..len].copy_from_slice(rest);
+        audit.buf[len] = 0;
+        if access(audit.buf.as_ptr() as *const c_char, F_OK) == 0 {
+            return Some(audit.buf.as_mut_ptr() as *mut c_char)
+        }
+    }
+    None
+}
+
+// takes the variable out of the environment, the child processes are not audited,
+// unsetenv only moves the pointers in environ, the string itself stays valid
+unsafe fn take_env<'a>(name: &core::ffi::CStr) -> &'a [u8] {
+    let val = c_str(getenv(name.as_ptr()));
+    unsetenv(name.as_ptr());
+    val
+}
+
+#[no_mangle]
+unsafe extern "C" fn la_version(version: c_uint) -> c_uint {
+    let audit = &mut *AUDIT.0.get();
+    audit.library_path = take_env(c"SHARUN_AUDIT_LIBRARY_PATH");
+    audit.is_debug = take_env(c"SHARUN_AUDIT_DEBUG") == b"1";
+    // loaded with LD_AUDIT by sharun, which saved the value it had before
+    let ld_audit = getenv(c"SHARUN_AUDIT_LD_AUDIT".as_ptr());
+    if !ld_audit.is_null() {
+        if *ld_audit == 0 {
+            unsetenv(c"LD_AUDIT".as_ptr());
+        } else {
+            setenv(c"LD_AUDIT".as_ptr(), ld_audit, 1);
+        }
+        unsetenv(c"SHARUN_AUDIT_LD_AUDIT".as_ptr());
+    }
+    version
+}
+
+#[no_mangle]
+unsafe extern "C" fn la_objsearch(name: *const c_char, _cookie: *mut usize, flag: c_uint) -> *mut c_char {
+    let audit = &mut *AUDIT.0.get();
+    let name_bytes = c_str(name);
+    if audit.is_debug {
+        log(&[b"sharun-audit: ", flag_name(flag), b": ", name_bytes, b"\n"])
+    }
+    if flag == LA_SER_ORIG && name_bytes.first() == Some(&b'/') {
+        if let Some(bundle_name) = find_in_bundle(audit, name_bytes) {
+            if audit.is_debug {
+                log(&[b"sharun-audit: ", name_bytes, b" -> ", c_str(bundle_name), b"\n"])
+            }
+            return bundle_name
+        }
+    }
+    name as *mut c_char
+}
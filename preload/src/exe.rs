@@ -0,0 +1,122 @@
+// Makes /proc/self/exe point to the binary in 'shared/bin' instead of sharun.
+
+use core::{ptr, ffi::{c_char, c_int}};
+
+use crate::{Global, PATH_MAX, c_str, real, getpid, malloc, pathmap::map_path};
+
+
+const AT_FDCWD: c_int = -100;
+
+static EXE: Global<([u8; PATH_MAX], usize)> = Global::new(([0; PATH_MAX], 0));
+
+pub(crate) unsafe fn init(exe: &[u8]) {
+    // the environment string is unset, so keep a copy
+    if !exe.is_empty() && exe.len() < PATH_MAX {
+        let (buf, len) = EXE.get_mut();
+        buf[..exe.len()].copy_from_slice(exe);
+        *len = exe.len()
+    }
+}
+
+fn exe() -> &'static [u8] {
+    let (buf, len) = EXE.get();
+    &buf[..*len]
+}
+
+fn write_num(buf: &mut [u8], mut num: u32) -> usize {
+    let mut digits = [0u8; 10];
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (num % 10) as u8;
+        len += 1;
+        num /= 10;
+        if num == 0 { break }
+    }
+    for i in 0..len {
+        buf[i] = digits[len - 1 - i]
+    }
+    len
+}
+
+unsafe fn is_self_exe(path: *const c_char) -> bool {
+    if exe().is_empty() {
+        return false
+    }
+    let path = c_str(path);
+    if path == b"/proc/self/exe" || path == b"/proc/thread-self/exe" {
+        return true
+    }
+    let mut pid_exe = [0u8; 32];
+    pid_exe[..6].copy_from_slice(b"/proc/");
+    let len = 6 + write_num(&mut pid_exe[6..], getpid() as u32);
+    pid_exe[len..len + 4].copy_from_slice(b"/exe");
+    path == &pid_exe[..len + 4]
+}
+
+unsafe fn copy_exe(buf: *mut c_char, bufsiz: usize) -> isize {
+    let exe = exe();
+    let len = exe.len().min(bufsiz);
+    ptr::copy_nonoverlapping(exe.as_ptr(), buf as *mut u8, len);
+    len as isize
+}
+
+unsafe fn dup_exe(resolved: *mut c_char) -> *mut c_char {
+    let exe = exe();
+    let resolved = if resolved.is_null() {
+        malloc(exe.len() + 1) as *mut c_char
+    } else { resolved };
+    if !resolved.is_null() {
+        ptr::copy_nonoverlapping(exe.as_ptr(), resolved as *mut u8, exe.len());
+        *resolved.add(exe.len()) = 0
+    }
+    resolved
+}
+
+#[no_mangle]
+unsafe extern "C" fn readlink(path: *const c_char, buf: *mut c_char, bufsiz: usize) -> isize {
+    readlinkat(AT_FDCWD, path, buf, bufsiz)
+}
+
+#[no_mangle]
+unsafe extern "C" fn readlinkat(dirfd: c_int, path: *const c_char, buf: *mut c_char, bufsiz: usize) -> isize {
+    if is_self_exe(path) {
+        return copy_exe(buf, bufsiz)
+    }
+    match real!(readlinkat: fn(c_int, *const c_char, *mut c_char, usize) -> isize) {
+        Some(readlinkat) => readlinkat(dirfd, path, buf, bufsiz),
+        None => -1
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn __readlink_chk(path: *const c_char, buf: *mut c_char, bufsiz: usize, _buflen: usize) -> isize {
+    readlinkat(AT_FDCWD, path, buf, bufsiz)
+}
+
+#[no_mangle]
+unsafe extern "C" fn __readlinkat_chk(dirfd: c_int, path: *const c_char, buf: *mut c_char, bufsiz: usize, _buflen: usize) -> isize {
+    readlinkat(dirfd, path, buf, bufsiz)
+}
+
+#[no_mangle]
+unsafe extern "C" fn realpath(path: *const c_char, resolved: *mut c_char) -> *mut c_char {
+    if is_self_exe(path) {
+        return dup_exe(resolved)
+    }
+    let mut buf = [0u8; PATH_MAX];
+    let path = map_path(path, &mut buf);
+    match real!(realpath: fn(*const c_char, *mut c_char) -> *mut c_char) {
+        Some(realpath) => realpath(path, resolved),
+        None => ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn __realpath_chk(path: *const c_char, resolved: *mut c_char, _resolvedlen: usize) -> *mut c_char {
+    realpath(path, resolved)
+}
+
+#[no_mangle]
+unsafe extern "C" fn canonicalize_file_name(path: *const c_char) -> *mut c_char {
+    realpath(path, ptr::null_mut())
+}
@@ -0,0 +1,106 @@
+// Preloaded by sharun into bundle binaries. It has no DT_NEEDED entries and
+// resolves the libc symbols it uses at load time, so the same library works
+// with both the glibc and the musl loader. With glibc < 2.34 sharun preloads
+// libdl.so.2 before it, where dlsym was.
+#![no_std]
+
+use core::{slice, cell::UnsafeCell, ffi::{c_char, c_int, c_void}};
+
+mod exe;
+mod pathmap;
+
+
+const PATH_MAX: usize = 4096;
+const RTLD_NEXT: *mut c_void = -1isize as *mut c_void;
+const ENOSYS: c_int = 38;
+
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn getenv(name: *const c_char) -> *mut c_char;
+    fn unsetenv(name: *const c_char) -> c_int;
+    fn getpid() -> c_int;
+    fn malloc(size: usize) -> *mut c_void;
+    fn __errno_location() -> *mut c_int;
+    fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+}
+
+// written once by the constructor, before the program can start any threads
+struct Global<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for Global<T> {}
+
+impl<T> Global<T> {
+    const fn new(val: T) -> Self {
+        Self(UnsafeCell::new(val))
+    }
+
+    fn get(&self) -> &T {
+        unsafe { &*self.0.get() }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get_mut(&self) -> &mut T {
+        &mut *self.0.get()
+    }
+}
+
+// looks up the next definition of the symbol once and caches it
+macro_rules! real {
+    ($name:ident: fn($($ty:ty),*) -> $ret:ty) => {{
+        use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        static SYM: AtomicUsize = AtomicUsize::new(0);
+        let mut sym = SYM.load(Relaxed);
+        if sym == 0 {
+            sym = $crate::dlsym($crate::RTLD_NEXT,
+                concat!(stringify!($name), "\0").as_ptr() as *const core::ffi::c_char) as usize;
+            SYM.store(sym, Relaxed);
+        }
+        if sym == 0 {
+            *$crate::__errno_location() = $crate::ENOSYS;
+            None
+        } else {
+            Some(core::mem::transmute::<usize, unsafe extern "C" fn($($ty),*) -> $ret>(sym))
+        }
+    }};
+}
+pub(crate) use real;
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+fn print_error(msg: &[u8]) {
+    unsafe { write(2, msg.as_ptr() as *const c_void, msg.len()) };
+}
+
+unsafe fn c_str<'a>(str: *const c_char) -> &'a [u8] {
+    if str.is_null() {
+        return &[]
+    }
+    let mut len = 0;
+    while *str.add(len) != 0 {
+        len += 1
+    }
+    slice::from_raw_parts(str as *const u8, len)
+}
+
+// takes the variable out of the environment, only this process is the bundle binary
+unsafe fn take_env<'a>(name: &core::ffi::CStr) -> &'a [u8] {
+    let val = c_str(getenv(name.as_ptr()));
+    if !val.is_empty() {
+        unsetenv(name.as_ptr());
+    }
+    val
+}
+
+extern "C" fn init() {
+    unsafe {
+        exe::init(take_env(c"SHARUN_EXE"));
+        pathmap::init(take_env(c"SHARUN_PATHMAP"));
+    }
+}
+
+#[used]
+#[link_section = ".init_array"]
+static INIT: extern "C" fn() = init;
@@ -0,0 +1,150 @@
+// Redirects hardcoded absolute paths into the bundle. The rules come from the
+// bundle's '.pathmap' file, sharun passes them as 'PREFIX=TARGET' lines with
+// absolute targets, 'PREFIX?=TARGET' applies only if the host path is missing.
+
+use core::{ptr, ffi::{c_char, c_int, c_uint, c_void}};
+
+use crate::{Global, PATH_MAX, c_str, print_error, real};
+
+
+const MAX_RULES: usize = 64;
+const F_OK: c_int = 0;
+
+#[derive(Clone, Copy)]
+struct Rule {
+    prefix: (usize, usize),
+    target: (usize, usize),
+    if_missing: bool
+}
+
+struct Rules {
+    data: [u8; 16384],
+    rules: [Rule; MAX_RULES],
+    len: usize
+}
+
+static RULES: Global<Rules> = Global::new(Rules {
+    data: [0; 16384],
+    rules: [Rule { prefix: (0, 0), target: (0, 0), if_missing: false }; MAX_RULES],
+    len: 0
+});
+
+fn trim_slash(path: &[u8]) -> &[u8] {
+    let mut len = path.len();
+    while len > 1 && path[len - 1] == b'/' {
+        len -= 1
+    }
+    &path[..len]
+}
+
+pub(crate) unsafe fn init(pathmap: &[u8]) {
+    let rules = RULES.get_mut();
+    if pathmap.len() > rules.data.len() {
+        print_error(b"sharun: .pathmap rules are larger than 16384 bytes, ignoring them\n");
+        return
+    }
+    rules.data[..pathmap.len()].copy_from_slice(pathmap);
+    let mut start = 0;
+    for line in pathmap.split(|&byte| byte == b'\n') {
+        let line_start = start;
+        start += line.len() + 1;
+        if rules.len == MAX_RULES {
+            print_error(b"sharun: .pathmap has more than 64 rules, ignoring the rest\n");
+            break
+        }
+        let Some(eq) = line.iter().position(|&byte| byte == b'=') else { continue };
+        let (mut prefix, target) = (&line[..eq], &line[eq + 1..]);
+        let if_missing = prefix.last() == Some(&b'?');
+        if if_missing {
+            prefix = &prefix[..prefix.len() - 1]
+        }
+        let (prefix, target) = (trim_slash(prefix), trim_slash(target));
+        if prefix.first() != Some(&b'/') || target.first() != Some(&b'/') {
+            continue
+        }
+        rules.rules[rules.len] = Rule {
+            prefix: (line_start, prefix.len()),
+            target: (line_start + eq + 1, target.len()),
+            if_missing
+        };
+        rules.len += 1
+    }
+}
+
+unsafe fn host_exists(path: *const c_char) -> bool {
+    match real!(access: fn(*const c_char, c_int) -> c_int) {
+        Some(access) => access(path, F_OK) == 0,
+        None => false
+    }
+}
+
+pub(crate) unsafe fn map_path(path: *const c_char, buf: &mut [u8; PATH_MAX]) -> *const c_char {
+    let rules = RULES.get();
+    let bytes = c_str(path);
+    if rules.len == 0 || bytes.first() != Some(&b'/') {
+        return path
+    }
+    for rule in &rules.rules[..rules.len] {
+        let prefix = &rules.data[rule.prefix.0..rule.prefix.0 + rule.prefix.1];
+        let target = &rules.data[rule.target.0..rule.target.0 + rule.target.1];
+        if !bytes.starts_with(prefix) ||
+            (prefix != b"/" && bytes.len() > prefix.len() && bytes[prefix.len()] != b'/') {
+            continue
+        }
+        let rest = &bytes[prefix.len()..];
+        if target.len() + rest.len() >= PATH_MAX || (rule.if_missing && host_exists(path)) {
+            return path
+        }
+        buf[..target.len()].copy_from_slice(target);
+        buf[target.len()..target.len() + rest.len()].copy_from_slice(rest);
+        buf[target.len() + rest.len()] = 0;
+        return buf.as_ptr() as *const c_char
+    }
+    path
+}
+
+// defines a wrapper that remaps the path argument and calls the next definition,
+// the arguments before the path are listed in brackets
+macro_rules! remap {
+    ($($name:ident[$($pre:ident: $pre_ty:ty),*](path $(, $arg:ident: $ty:ty)*) -> $ret:ty = $fail:expr;)*) => {$(
+        #[no_mangle]
+        unsafe extern "C" fn $name($($pre: $pre_ty,)* path: *const c_char $(, $arg: $ty)*) -> $ret {
+            let mut buf = [0u8; PATH_MAX];
+            let path = map_path(path, &mut buf);
+            match real!($name: fn($($pre_ty,)* *const c_char $(, $ty)*) -> $ret) {
+                Some(real) => real($($pre,)* path $(, $arg)*),
+                None => $fail
+            }
+        }
+    )*};
+}
+
+// the optional mode argument of open is read as a regular one, it is only used with O_CREAT and O_TMPFILE
+remap! {
+    open[](path, flags: c_int, mode: c_uint) -> c_int = -1;
+    open64[](path, flags: c_int, mode: c_uint) -> c_int = -1;
+    __open_2[](path, flags: c_int) -> c_int = -1;
+    __open64_2[](path, flags: c_int) -> c_int = -1;
+    openat[dirfd: c_int](path, flags: c_int, mode: c_uint) -> c_int = -1;
+    openat64[dirfd: c_int](path, flags: c_int, mode: c_uint) -> c_int = -1;
+    __openat_2[dirfd: c_int](path, flags: c_int) -> c_int = -1;
+    __openat64_2[dirfd: c_int](path, flags: c_int) -> c_int = -1;
+    fopen[](path, mode: *const c_char) -> *mut c_void = ptr::null_mut();
+    fopen64[](path, mode: *const c_char) -> *mut c_void = ptr::null_mut();
+    opendir[](path) -> *mut c_void = ptr::null_mut();
+    access[](path, mode: c_int) -> c_int = -1;
+    faccessat[dirfd: c_int](path, mode: c_int, flags: c_int) -> c_int = -1;
+    stat[](path, buf: *mut c_void) -> c_int = -1;
+    stat64[](path, buf: *mut c_void) -> c_int = -1;
+    lstat[](path, buf: *mut c_void) -> c_int = -1;
+    lstat64[](path, buf: *mut c_void) -> c_int = -1;
+    fstatat[dirfd: c_int](path, buf: *mut c_void, flags: c_int) -> c_int = -1;
+    fstatat64[dirfd: c_int](path, buf: *mut c_void, flags: c_int) -> c_int = -1;
+    statx[dirfd: c_int](path, flags: c_int, mask: c_uint, buf: *mut c_void) -> c_int = -1;
+    __xstat[ver: c_int](path, buf: *mut c_void) -> c_int = -1;
+    __xstat64[ver: c_int](path, buf: *mut c_void) -> c_int = -1;
+    __lxstat[ver: c_int](path, buf: *mut c_void) -> c_int = -1;
+    __lxstat64[ver: c_int](path, buf: *mut c_void) -> c_int = -1;
+    __fxstatat[ver: c_int, dirfd: c_int](path, buf: *mut c_void, flags: c_int) -> c_int = -1;
+    __fxstatat64[ver: c_int, dirfd: c_int](path, buf: *mut c_void, flags: c_int) -> c_int = -1;
+}
@@ -0,0 +1,282 @@
+// Built-in dependency collection: copies the binaries and the DT_NEEDED closure of them
+// into a sharun dir with the same layout as lib4bin, without bash, file, ldd or patchelf.
+
+use std::{
+    env,
+    collections::HashSet,
+    fs::{self, read, read_to_string},
+    path::{Path, PathBuf},
+    os::unix::fs::symlink,
+    io::{Result, Error, ErrorKind::{InvalidData, InvalidInput, NotFound}}
+};
+
+use goblin::elf::Elf;
+
+use crate::{SHARUN_NAME, MULTIARCH, ElfInfo, get_elf_info, get_host_lib_dirs, is_exe, is_script, which};
+
+
+// after the ld.so.cache dirs, like the loader itself
+const DEFAULT_LIB_DIRS: [&str; 6] = ["/lib64", "/usr/lib64", "/lib32", "/usr/lib32", "/lib", "/usr/lib"];
+
+// the data dirs of the host and of the sharun dir
+const DATA_DIRS: [(&str, &str); 3] = [("/usr/share/", "share"), ("/usr/local/share/", "share"), ("/etc/", "etc")];
+
+pub struct Collected {
+    pub bins: usize,
+    pub libs: usize,
+    pub data: usize,
+    pub missing: Vec<String>
+}
+
+pub struct List {
+    pub files: Vec<PathBuf>,
+    pub data: Vec<PathBuf>
+}
+
+// '<bin|lib|data> <path>' lines of 'sharun trace', or just paths
+pub fn read_list(path: &Path) -> Result<List> {
+    let mut list = List { files: Vec::new(), data: Vec::new() };
+    let data = read_to_string(path).map_err(|err| Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+    for (line_num, line) in data.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        match line.split_once(' ') {
+            Some(("bin" | "lib", file)) => list.files.push(file.into()),
+            Some(("data", file)) => list.data.push(file.into()),
+            _ if line.starts_with('/') => list.files.push(line.into()),
+            _ => return Err(Error::new(InvalidData, format!("{}:{}: invalid line", path.display(), line_num + 1)))
+        }
+    }
+    Ok(list)
+}
+
+fn get_lib_dir(elf_info: &ElfInfo) -> &'static str {
+    if elf_info.is_elf32 && cfg!(target_pointer_width = "64") { "lib32" } else { "lib" }
+}
+
+// the dir of the library relative to 'shared/lib', like get_lib_dst_dir_pth of lib4bin
+fn get_lib_subdir(src_dir: &Path) -> PathBuf {
+    let mut dir = src_dir.to_string_lossy().to_string();
+    for prefix in ["/usr", "/opt"] {
+        if let Some(stripped) = dir.strip_prefix(prefix) {
+            dir = stripped.into()
+        }
+    }
+    for prefix in ["/lib64", "/lib32", "/lib"] {
+        if let Some(stripped) = dir.strip_prefix(prefix) {
+            dir = stripped.into();
+            break
+        }
+    }
+    if let Some(stripped) = dir.strip_prefix('/') {
+        let (first, rest) = stripped.split_once('/').unwrap_or((stripped, ""));
+        if first.ends_with("-linux-gnu") {
+            dir = if rest.is_empty() { String::new() } else { format!("/{rest}") }
+        }
+    }
+    PathBuf::from(dir.trim_start_matches('/'))
+}
+
+fn get_origin_dirs(paths: &[&str], origin: &str, elf_info: &ElfInfo) -> Vec<String> {
+    let lib = if elf_info.is_elf32 { "lib" } else { "lib64" };
+    paths.iter().flat_map(|path| path.split(':'))
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| dir.replace("${ORIGIN}", origin).replace("$ORIGIN", origin)
+            .replace("${LIB}", lib).replace("$LIB", lib))
+        .collect()
+}
+
+struct Collector {
+    dst_dir: PathBuf,
+    host_lib_dirs: Vec<String>,
+    seen: HashSet<PathBuf>,
+    collected: Collected
+}
+
+impl Collector {
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        if dst.symlink_metadata().is_ok() {
+            return Ok(())
+        }
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?
+        }
+        fs::copy(src, dst).map_err(|err| Error::new(err.kind(), format!("{} -> {}: {err}", src.display(), dst.display())))?;
+        Ok(())
+    }
+
+    fn link(&self, target: &Path, link: &Path) -> Result<()> {
+        if link.symlink_metadata().is_ok() {
+            return Ok(())
+        }
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?
+        }
+        symlink(target, link)
+    }
+
+    // the search order of the loader: DT_RPATH without DT_RUNPATH, LD_LIBRARY_PATH, DT_RUNPATH,
+    // ld.so.conf and the default dirs, skipping the libraries of another class or machine
+    fn find_lib(&self, name: &str, elf: &Elf, origin: &str, elf_info: &ElfInfo, rpaths: &[String]) -> Option<PathBuf> {
+        let is_compatible = |path: &Path| get_elf_info(&path.to_string_lossy())
+            .is_ok_and(|info| info.is_elf32 == elf_info.is_elf32 && info.machine == elf_info.machine);
+        if name.contains('/') {
+            return Some(PathBuf::from(name)).filter(|path| is_compatible(path))
+        }
+        let library_path = env::var("LD_LIBRARY_PATH").unwrap_or_default();
+        let mut lib_dirs: Vec<String> = if elf.runpaths.is_empty() { rpaths.to_vec() } else { Vec::new() };
+        lib_dirs.extend(library_path.split(':').filter(|dir| !dir.is_empty()).map(String::from));
+        lib_dirs.extend(get_origin_dirs(&elf.runpaths, origin, elf_info));
+        lib_dirs.extend(self.host_lib_dirs.iter().cloned());
+        lib_dirs.extend([format!("/lib/{MULTIARCH}"), format!("/usr/lib/{MULTIARCH}")]);
+        lib_dirs.extend(DEFAULT_LIB_DIRS.iter().map(|dir| dir.to_string()));
+        lib_dirs.iter().map(|dir| Path::new(dir).join(name)).find(|path| is_compatible(path))
+    }
+
+    // copies the real file under its real name, and links the needed name to it
+    fn add_lib(&mut self, path: &Path, name: &str, elf_info: &ElfInfo, to_root: bool) -> Result<bool> {
+        let real_path = path.canonicalize()?;
+        let src_dir = real_path.parent().unwrap_or(Path::new("/"));
+        let lib_dir = self.dst_dir.join("shared").join(get_lib_dir(elf_info));
+        let dst_dir = if to_root { lib_dir } else { lib_dir.join(get_lib_subdir(src_dir)) };
+        let real_name = real_path.file_name().unwrap_or_default();
+        self.copy(&real_path, &dst_dir.join(real_name))?;
+        if real_name != name {
+            self.link(Path::new(real_name), &dst_dir.join(name))?
+        }
+        let is_new = self.seen.insert(real_path);
+        if is_new {
+            self.collected.libs += 1
+        }
+        Ok(is_new)
+    }
+
+    fn add_deps(&mut self, path: &Path, elf_info: &ElfInfo) -> Result<()> {
+        if let Some(interp) = &elf_info.interp {
+            let name = Path::new(interp).file_name().unwrap_or_default().to_string_lossy().to_string();
+            if Path::new(interp).exists() {
+                self.add_lib(Path::new(interp), &name, elf_info, true)?;
+            } else {
+                self.collected.missing.push(format!("{interp} (interpreter of {})", path.display()))
+            }
+        }
+        // the rpaths of the loading objects apply to the libraries they load
+        let mut queue = vec![(path.to_path_buf(), Vec::new())];
+        while let Some((elf_path, mut rpaths)) = queue.pop() {
+            let elf_bytes = read(&elf_path)?;
+            let elf = Elf::parse(&elf_bytes).map_err(|err| Error::new(InvalidInput, format!("{}: {err}", elf_path.display())))?;
+            let origin = elf_path.canonicalize()?.parent().unwrap_or(Path::new("/")).to_string_lossy().to_string();
+            if elf.runpaths.is_empty() {
+                rpaths.splice(0..0, get_origin_dirs(&elf.rpaths, &origin, elf_info));
+            }
+            for &name in &elf.libraries {
+                match self.find_lib(name, &elf, &origin, elf_info, &rpaths) {
+                    Some(lib_path) => {
+                        let name = Path::new(name).file_name().unwrap_or_default().to_string_lossy().to_string();
+                        let is_interp = elf_info.interp.as_ref()
+                            .and_then(|interp| Path::new(interp).canonicalize().ok())
+                            .is_some_and(|interp| lib_path.canonicalize().is_ok_and(|lib_path| lib_path == interp));
+                        if self.add_lib(&lib_path, &name, elf_info, is_interp)? {
+                            queue.push((lib_path, rpaths.clone()))
+                        }
+                    }
+                    None => self.collected.missing.push(format!("{name} (needed by {})", elf_path.display()))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_bin(&mut self, path: &Path, hard_links: bool) -> Result<()> {
+        let name = path.file_name().unwrap_or_default();
+        let real_path = path.canonicalize()?;
+        let real_name = real_path.file_name().unwrap_or_default();
+        if name == SHARUN_NAME || real_name == SHARUN_NAME {
+            return Ok(())
+        }
+        let elf_info = match get_elf_info(&real_path.to_string_lossy()) {
+            Ok(elf_info) => Some(elf_info),
+            Err(_) if is_script(&real_path).unwrap_or(false) => None,
+            Err(err) => return Err(Error::new(err.kind(), format!("{}: {err}", path.display())))
+        };
+        let is_lib = name.to_string_lossy().contains(".so");
+        let is_dynamic = elf_info.as_ref().is_some_and(|elf_info| elf_info.interp.is_some());
+        if let Some(elf_info) = elf_info.as_ref().filter(|_| is_lib) {
+            // dlopened libraries are passed explicitly, they keep their dirs
+            if self.add_lib(path, &name.to_string_lossy(), elf_info, false)? {
+                self.add_deps(&real_path, elf_info)?
+            }
+            return Ok(())
+        }
+
+        // static binaries and scripts run without sharun, like in lib4bin
+        let bin_dir = if is_dynamic { self.dst_dir.join("shared/bin") } else { self.dst_dir.join("bin") };
+        self.copy(&real_path, &bin_dir.join(real_name))?;
+        if real_name != name {
+            self.link(Path::new(real_name), &bin_dir.join(name))?
+        }
+        self.collected.bins += 1;
+        if let Some(elf_info) = elf_info.as_ref().filter(|_| is_dynamic) {
+            self.add_deps(&real_path, elf_info)?;
+            let bin_link = self.dst_dir.join("bin").join(name);
+            if bin_link.symlink_metadata().is_err() {
+                fs::create_dir_all(self.dst_dir.join("bin"))?;
+                if hard_links {
+                    fs::hard_link(self.dst_dir.join(SHARUN_NAME), &bin_link)?
+                } else {
+                    symlink(Path::new("..").join(SHARUN_NAME), &bin_link)?
+                }
+            }
+            let lib_dir = get_lib_dir(elf_info);
+            self.link(&Path::new("shared").join(lib_dir), &self.dst_dir.join(lib_dir))?
+        }
+        Ok(())
+    }
+}
+
+pub fn collect(sharun: &Path, files: &[PathBuf], data_files: &[PathBuf], dst_dir: &Path, hard_links: bool) -> Result<Collected> {
+    // all of them are checked before anything is copied
+    let mut paths = Vec::new();
+    for file in files {
+        let path = if file.to_string_lossy().contains('/') {
+            file.clone()
+        } else {
+            which(file).ok_or_else(||
+                Error::new(NotFound, format!("{}: not found in PATH", file.display())))?
+        };
+        if !path.exists() {
+            return Err(Error::new(NotFound, format!("{}: no such file", path.display())))
+        }
+        paths.push(path)
+    }
+    let mut data_paths = Vec::new();
+    for file in data_files {
+        let (prefix, dir) = DATA_DIRS.iter().find(|(prefix, _)| file.starts_with(prefix))
+            .ok_or_else(|| Error::new(InvalidInput, format!("{}: not in /usr/share or /etc", file.display())))?;
+        data_paths.push((file, Path::new(dir).join(file.strip_prefix(prefix).unwrap_or(file))))
+    }
+    fs::create_dir_all(dst_dir)?;
+    let dst_dir = dst_dir.canonicalize()?;
+    if !is_exe(&dst_dir.join(SHARUN_NAME)) {
+        fs::copy(sharun, dst_dir.join(SHARUN_NAME))?;
+    }
+    let mut collector = Collector {
+        dst_dir,
+        host_lib_dirs: Vec::new(),
+        seen: HashSet::new(),
+        collected: Collected { bins: 0, libs: 0, data: 0, missing: Vec::new() }
+    };
+    for path in paths {
+        if let Ok(elf_info) = get_elf_info(&path.to_string_lossy()) {
+            collector.host_lib_dirs = get_host_lib_dirs(elf_info.is_elf32, elf_info.machine)
+        }
+        collector.add_bin(&path, hard_links)?
+    }
+    for (src, dst) in data_paths {
+        collector.copy(src, &collector.dst_dir.join(dst))?;
+        collector.collected.data += 1
+    }
+    Ok(collector.collected)
+}
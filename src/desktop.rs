@@ -0,0 +1,338 @@
+// Desktop Entry files: https://specifications.freedesktop.org/desktop-entry-spec/latest/
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::read_to_string,
+    io::{Result, Error, ErrorKind::{InvalidData, NotFound}}
+};
+
+use crate::which;
+
+
+// the terminal and the arguments to run a command in it
+const TERMINALS: [(&str, &[&str]); 10] = [
+    ("xdg-terminal-exec", &[]),
+    ("x-terminal-emulator", &["-e"]),
+    ("gnome-terminal", &["--"]),
+    ("konsole", &["-e"]),
+    ("xfce4-terminal", &["-x"]),
+    ("kitty", &[]),
+    ("alacritty", &["-e"]),
+    ("foot", &[]),
+    ("wezterm", &["start", "--"]),
+    ("xterm", &["-e"])
+];
+
+
+pub fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(char) => { unescaped.push('\\'); unescaped.push(char) }
+            None => unescaped.push('\\')
+        }
+    }
+    unescaped
+}
+
+pub fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, char) in value.chars().enumerate() {
+        match char {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            ' ' if i == 0 => escaped.push_str("\\s"),
+            char => escaped.push(char)
+        }
+    }
+    escaped
+}
+
+// splits the unescaped value of the Exec key into arguments, field codes are left as is
+pub fn split_exec(exec: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            ' ' | '\t' | '\n' => args.extend(arg.take()),
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(char @ ('"' | '`' | '$' | '\\')) => arg.push(char),
+                            Some(char) => { arg.push('\\'); arg.push(char) }
+                            None => return Err(Error::new(InvalidData, "unterminated quote in Exec key"))
+                        }
+                        Some(char) => arg.push(char),
+                        None => return Err(Error::new(InvalidData, "unterminated quote in Exec key"))
+                    }
+                }
+            }
+            char => arg.get_or_insert_with(String::new).push(char)
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+pub fn quote_exec_arg(arg: &str) -> String {
+    const RESERVED: &str = " \t\n\"'\\><~|&;$*?#()`";
+    if !arg.is_empty() && !arg.contains(|char| RESERVED.contains(char)) {
+        return arg.into()
+    }
+    let mut quoted = String::from("\"");
+    for char in arg.chars() {
+        if matches!(char, '"' | '`' | '$' | '\\') {
+            quoted.push('\\')
+        }
+        quoted.push(char)
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub struct DesktopEntry {
+    pub path: PathBuf,
+    // unescaped values of the [Desktop Entry] group
+    keys: HashMap<String, String>
+}
+
+impl DesktopEntry {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = read_to_string(path)?;
+        let mut keys = HashMap::new();
+        let mut group = None;
+        let mut has_entry = false;
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                group = Some(&line[1..line.len() - 1]);
+                has_entry |= group == Some("Desktop Entry");
+                continue
+            }
+            if group != Some("Desktop Entry") {
+                continue
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                // the first one wins, duplicate keys are not allowed
+                keys.entry(key.trim().into()).or_insert_with(|| unescape_value(value.trim_start()));
+            }
+        }
+        if !has_entry {
+            return Err(Error::new(InvalidData, "no [Desktop Entry] group"))
+        }
+        Ok(Self { path: path.into(), keys })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.keys.get(key).map(|value| value.as_str())
+    }
+
+    pub fn is_true(&self, key: &str) -> bool {
+        self.get(key) == Some("true")
+    }
+
+    // AppRun is started once, so the list field codes get all the arguments and the single ones
+    // only the first, without them the arguments are appended like to any other command
+    pub fn expand_exec(&self, args: &[OsString]) -> Result<Vec<OsString>> {
+        let exec = self.get("Exec").ok_or_else(|| Error::new(NotFound, "no Exec key"))?;
+        let mut has_files = false;
+        let mut expanded = Vec::new();
+        for arg in split_exec(exec)? {
+            match arg.as_str() {
+                "%F" | "%U" => {
+                    has_files = true;
+                    expanded.extend_from_slice(args)
+                }
+                "%f" | "%u" => {
+                    has_files = true;
+                    expanded.extend(args.first().cloned())
+                }
+                "%i" => if let Some(icon) = self.get("Icon").filter(|icon| !icon.is_empty()) {
+                    expanded.extend(["--icon".into(), icon.into()])
+                }
+                _ => {
+                    let mut expanded_arg = OsString::new();
+                    let mut chars = arg.chars();
+                    while let Some(char) = chars.next() {
+                        if char != '%' {
+                            expanded_arg.push(char.to_string());
+                            continue
+                        }
+                        match chars.next() {
+                            Some('%') => expanded_arg.push("%"),
+                            Some('c') => expanded_arg.push(self.get("Name").unwrap_or_default()),
+                            Some('k') => expanded_arg.push(&self.path),
+                            Some('f' | 'F' | 'u' | 'U') => {
+                                has_files = true;
+                                expanded_arg.push(args.first().map(|arg| arg.as_os_str()).unwrap_or_default())
+                            }
+                            // deprecated and unknown field codes are removed
+                            _ => {}
+                        }
+                    }
+                    if arg.is_empty() || !expanded_arg.is_empty() {
+                        expanded.push(expanded_arg)
+                    }
+                }
+            }
+        }
+        if expanded.is_empty() {
+            return Err(Error::new(InvalidData, "empty Exec key"))
+        }
+        if !has_files {
+            expanded.extend_from_slice(args)
+        }
+        Ok(expanded)
+    }
+}
+
+// the first desktop file by name, so the choice does not depend on the directory order
+pub fn find_desktop_entry(dir: &str) -> Result<DesktopEntry> {
+    let mut paths: Vec<PathBuf> = Path::new(dir).read_dir()?.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "desktop"))
+        .collect();
+    paths.sort();
+    let mut last_err = Error::new(NotFound, format!("no desktop file in '{dir}'"));
+    for path in paths {
+        match DesktopEntry::read(&path) {
+            Ok(entry) if entry.get("Exec").is_some() => return Ok(entry),
+            Ok(_) => last_err = Error::new(NotFound, format!("{}: no Exec key", path.display())),
+            Err(err) => last_err = Error::new(err.kind(), format!("{}: {err}", path.display()))
+        }
+    }
+    Err(last_err)
+}
+
+pub fn get_terminal() -> Option<Vec<OsString>> {
+    if let Some(terminal) = env::var_os("TERMINAL").filter(|terminal| !terminal.is_empty()) {
+        return Some(vec![terminal, "-e".into()])
+    }
+    TERMINALS.iter().find_map(|(terminal, args)| which(terminal).map(|path| {
+        let mut command = vec![path.into_os_string()];
+        command.extend(args.iter().map(OsStr::new).map(OsString::from));
+        command
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(exec: &str) -> DesktopEntry {
+        DesktopEntry {
+            path: PathBuf::from("/opt/app/app.desktop"),
+            keys: HashMap::from([
+                ("Exec".into(), unescape_value(exec)),
+                ("Name".into(), "My App".into()),
+                ("Icon".into(), "app".into())
+            ])
+        }
+    }
+
+    fn expand(exec: &str, args: &[&str]) -> Vec<String> {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        entry(exec).expand_exec(&args).unwrap().into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn unescape() {
+        assert_eq!(unescape_value(r"a\sb\nc\td\re\\f"), "a b\nc\td\re\\f");
+        // unknown escapes and a trailing backslash are kept
+        assert_eq!(unescape_value(r"\x\"), r"\x\");
+        assert_eq!(unescape_value(&escape_value(" a\\b\n\t")), " a\\b\n\t");
+        assert_eq!(escape_value(" a b"), r"\sa b");
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_exec("app  --flag\targ").unwrap(), ["app", "--flag", "arg"]);
+        assert_eq!(split_exec(r#""/opt/my app/bin" "a \"b\" \`c\` \$d \\e" "" %U"#).unwrap(),
+            ["/opt/my app/bin", r#"a "b" `c` $d \e"#, "", "%U"]);
+        // quotes inside a word and an unknown escape in quotes
+        assert_eq!(split_exec(r#"--dir="a b"/c "\n""#).unwrap(), ["--dir=a b/c", r"\n"]);
+        assert!(split_exec(r#"app "unterminated"#).is_err());
+        assert!(split_exec(r#"app "a\"#).is_err());
+    }
+
+    #[test]
+    fn split_escaped_value() {
+        // the string escapes of the key value come first, then the quoting of the Exec key
+        assert_eq!(split_exec(&unescape_value(r#""/opt/my\sapp" "a \\"q\\" \\\\x""#)).unwrap(),
+            ["/opt/my app", r#"a "q" \x"#]);
+    }
+
+    #[test]
+    fn quote() {
+        for arg in ["plain", "", "a b", r#"q"uo`te$s\"#, "semi;colon", "a'b"] {
+            let quoted = quote_exec_arg(arg);
+            assert_eq!(split_exec(&format!("app {quoted}")).unwrap(), ["app", arg]);
+        }
+        assert_eq!(quote_exec_arg("plain"), "plain");
+    }
+
+    #[test]
+    fn expand_files() {
+        assert_eq!(expand("app %f", &["a", "b"]), ["app", "a"]);
+        assert_eq!(expand("app %u", &["a", "b"]), ["app", "a"]);
+        assert_eq!(expand("app %F --end", &["a", "b"]), ["app", "a", "b", "--end"]);
+        assert_eq!(expand("app %U", &["a", "b"]), ["app", "a", "b"]);
+        assert_eq!(expand("app %U", &[]), ["app"]);
+        assert_eq!(expand("app --file=%f", &["a", "b"]), ["app", "--file=a"]);
+    }
+
+    #[test]
+    fn expand_without_field_codes() {
+        assert_eq!(expand("app --flag", &["a", "b"]), ["app", "--flag", "a", "b"]);
+        assert_eq!(expand(r#""/opt/my app" "x y""#, &["a"]), ["/opt/my app", "x y", "a"]);
+    }
+
+    #[test]
+    fn expand_other_codes() {
+        assert_eq!(expand("app %i %c %k", &[]), ["app", "--icon", "app", "My App", "/opt/app/app.desktop"]);
+        assert_eq!(expand("app 100%% --title=%c", &[]), ["app", "100%", "--title=My App"]);
+        // deprecated ones are removed, the arguments are still appended
+        assert_eq!(expand("app %d %D %n %N %v %m x%dy", &["a"]), ["app", "xy", "a"]);
+        let mut no_icon = entry("app %i");
+        no_icon.keys.remove("Icon");
+        assert_eq!(no_icon.expand_exec(&[]).unwrap(), ["app"]);
+        assert!(entry("%d").expand_exec(&[]).is_err());
+    }
+
+    #[test]
+    fn read_groups() {
+        let path = env::temp_dir().join(format!("sharun-test-{}.desktop", std::process::id()));
+        std::fs::write(&path, "# comment\n[Desktop Action new]\nExec=other\n\n[Desktop Entry]\n\
+            Name = App\nExec=app\\s%U\nExec=duplicate\nTerminal=true\n[Desktop Action x]\nName=X\n").unwrap();
+        let entry = DesktopEntry::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let entry = entry.unwrap();
+        assert_eq!(entry.get("Exec"), Some("app %U"));
+        assert_eq!(entry.get("Name"), Some("App"));
+        assert!(entry.is_true("Terminal"));
+    }
+}
@@ -0,0 +1,356 @@
+// Pre-launch hooks from the .hooks dir of the bundle: first the files of .hooks itself,
+// then of .hooks/<bin_name>, in the order of their names. *.sh hooks are sourced by a shell,
+// other executable hooks print KEY=VALUE lines, both change the environment of the binary.
+
+use std::{
+    env,
+    thread,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+    ffi::{OsStr, OsString},
+    process::{Command, Stdio},
+    os::{fd::AsRawFd, unix::{ffi::{OsStrExt, OsStringExt}, process::CommandExt}},
+    io::{Read, Result, Error, ErrorKind::{InvalidInput, Interrupted, Other, TimedOut}}
+};
+
+use nix::libc;
+
+use crate::{is_exe, which, get_env_var};
+
+
+const DEFAULT_TIMEOUT: u64 = 10;
+// set by the shell itself, not by the hook
+const SHELL_VARS: [&str; 5] = ["PWD", "OLDPWD", "SHLVL", "_", "SHARUN_NO_HOOKS"];
+
+fn get_hooks(hooks_dir: &Path) -> Vec<PathBuf> {
+    let mut hooks: Vec<PathBuf> = hooks_dir.read_dir().map(|dir| dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() &&
+            (path.extension().is_some_and(|ext| ext == "sh") || is_exe(path)))
+        .collect()).unwrap_or_default();
+    hooks.sort();
+    hooks
+}
+
+fn get_shell(sharun_dir: &str) -> Option<PathBuf> {
+    ["sh", "bash"].iter().map(|shell| Path::new(sharun_dir).join("bin").join(shell))
+        .find(|shell| is_exe(shell))
+        .or_else(|| which("sh"))
+        .or_else(|| Some(PathBuf::from("/bin/sh")).filter(|shell| is_exe(shell)))
+}
+
+fn is_var_name(name: &[u8]) -> bool {
+    !name.is_empty() && !name[0].is_ascii_digit() &&
+        name.iter().all(|&byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+// stdout is read on this thread: sharun may exec the binary in userland after the hooks,
+// so it must not leave threads behind
+fn run_hook(command: &mut Command, timeout: Duration) -> Result<Vec<u8>> {
+    // SHARUN_NO_HOOKS keeps the bundle binaries started by the hook from running the hooks again
+    let mut child = command
+        .env("SHARUN_NO_HOOKS", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let mut stdout = child.stdout.take().ok_or_else(|| Error::new(Other, "failed to take stdout"))?;
+    let deadline = Instant::now() + timeout;
+    let mut output = Vec::new();
+    let mut is_eof = false;
+    let mut status = None;
+    // its background processes may still hold stdout after it exits
+    while !is_eof || status.is_none() {
+        if status.is_none() {
+            status = child.try_wait()?;
+            if let Some(status) = status.filter(|status| !status.success()) {
+                return Err(Error::new(Other, format!("failed with {status}")))
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+            let _ = child.wait();
+            return Err(Error::new(TimedOut, match status {
+                Some(_) => format!("stdout is not closed after {}s", timeout.as_secs()),
+                None => format!("timed out after {}s", timeout.as_secs())
+            }))
+        }
+        // briefly, so the exit of the hook is noticed too
+        let wait = remaining.min(Duration::from_millis(10));
+        if is_eof {
+            thread::sleep(wait);
+            continue
+        }
+        let mut pollfd = libc::pollfd { fd: stdout.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pollfd, 1, wait.as_millis() as libc::c_int) } <= 0 {
+            continue
+        }
+        let mut buf = [0u8; 8192];
+        match stdout.read(&mut buf) {
+            Ok(0) => is_eof = true,
+            Ok(len) => output.extend_from_slice(&buf[..len]),
+            Err(err) if err.kind() == Interrupted => {}
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(output)
+}
+
+// a quoted word of 'export -p' up to the end of the line: '...', "...", $'...' of bash and \x
+fn parse_word(data: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let mut word = Vec::new();
+    while let Some(&byte) = data.get(*pos) {
+        match byte {
+            b'\n' => break,
+            b'\'' => {
+                let len = data[*pos + 1..].iter().position(|&byte| byte == b'\'')?;
+                word.extend_from_slice(&data[*pos + 1..*pos + 1 + len]);
+                *pos += len + 2
+            }
+            b'"' => {
+                *pos += 1;
+                loop {
+                    match *data.get(*pos)? {
+                        b'"' => break,
+                        b'\\' if matches!(data.get(*pos + 1), Some(b'$' | b'`' | b'"' | b'\\')) => {
+                            word.push(data[*pos + 1]);
+                            *pos += 1
+                        }
+                        b'\\' if data.get(*pos + 1) == Some(&b'\n') => *pos += 1,
+                        byte => word.push(byte)
+                    }
+                    *pos += 1
+                }
+                *pos += 1
+            }
+            b'$' if data.get(*pos + 1) == Some(&b'\'') => {
+                *pos += 2;
+                loop {
+                    match *data.get(*pos)? {
+                        b'\'' => break,
+                        b'\\' => {
+                            *pos += 1;
+                            let escape = *data.get(*pos)?;
+                            let radix = match escape {
+                                b'0'..=b'7' => 8,
+                                b'x' => 16,
+                                _ => 0
+                            };
+                            if radix == 0 {
+                                word.push(match escape {
+                                    b'a' => 0x07,
+                                    b'b' => 0x08,
+                                    b'e' | b'E' => 0x1b,
+                                    b'f' => 0x0c,
+                                    b'n' => b'\n',
+                                    b'r' => b'\r',
+                                    b't' => b'\t',
+                                    b'v' => 0x0b,
+                                    escape => escape
+                                })
+                            } else {
+                                // up to 3 octal digits or 2 hex digits after \x
+                                let mut num = if radix == 8 { escape - b'0' } else { 0 };
+                                for _ in 0..2 {
+                                    match data.get(*pos + 1).and_then(|&byte| (byte as char).to_digit(radix)) {
+                                        Some(digit) => {
+                                            num = num.wrapping_mul(radix as u8).wrapping_add(digit as u8);
+                                            *pos += 1
+                                        }
+                                        None => break
+                                    }
+                                }
+                                word.push(num)
+                            }
+                        }
+                        byte => word.push(byte)
+                    }
+                    *pos += 1
+                }
+                *pos += 1
+            }
+            b'\\' => {
+                if let Some(&byte) = data.get(*pos + 1).filter(|&&byte| byte != b'\n') {
+                    word.push(byte)
+                }
+                *pos += 2
+            }
+            byte => {
+                word.push(byte);
+                *pos += 1
+            }
+        }
+    }
+    Some(word)
+}
+
+// 'export NAME=value' lines of dash and busybox sh or 'declare -x NAME=value' of bash,
+// 'export NAME' is an exported but unset variable
+fn parse_exports(data: &[u8]) -> Option<Vec<(OsString, OsString)>> {
+    let mut vars = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let line = &data[pos..];
+        let prefix_len = if line.starts_with(b"export ") {
+            7
+        } else if line.starts_with(b"declare -") {
+            line.iter().position(|&byte| byte == b' ')
+                .and_then(|len| line[len + 1..].iter().position(|&byte| byte == b' ').map(|next| len + next + 2))?
+        } else {
+            return None
+        };
+        pos += prefix_len;
+        let name_len = data[pos..].iter().position(|&byte| byte == b'=' || byte == b'\n')
+            .unwrap_or(data.len() - pos);
+        let name = &data[pos..pos + name_len];
+        pos += name_len;
+        if data.get(pos) == Some(&b'=') {
+            pos += 1;
+            let value = parse_word(data, &mut pos)?;
+            vars.push((OsStr::from_bytes(name).into(), OsString::from_vec(value)))
+        }
+        // the newline
+        pos += 1
+    }
+    Some(vars)
+}
+
+// the environment is compared before and after the hook in the same shell,
+// so only the changes of the hook are applied and nothing but the shell is needed
+fn source_hook(shell: &Path, hook: &Path, timeout: Duration) -> Result<()> {
+    let output = run_hook(Command::new(shell)
+        .arg("-c").arg("export -p; printf '\\0'; . \"$1\" >&2; export -p; printf '\\0'")
+        .arg("sh").arg(hook), timeout)?;
+    let mut parts = output.split(|&byte| byte == 0);
+    let (Some(old_exports), Some(new_exports), Some([]), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(Error::new(Other, "the hook exited the shell"))
+    };
+    let (Some(old_vars), Some(new_vars)) = (parse_exports(old_exports), parse_exports(new_exports)) else {
+        return Err(Error::new(Other, "failed to parse the environment"))
+    };
+    let is_hook_var = |key: &OsStr| is_var_name(key.as_bytes()) &&
+        !SHELL_VARS.iter().any(|var| key.as_bytes() == var.as_bytes());
+    for (key, _) in &old_vars {
+        if is_hook_var(key) && !new_vars.iter().any(|(new_key, _)| new_key == key) {
+            env::remove_var(key)
+        }
+    }
+    for (key, val) in &new_vars {
+        if is_hook_var(key) && !old_vars.iter().any(|(old_key, old_val)| old_key == key && old_val == val) {
+            env::set_var(key, val)
+        }
+    }
+    Ok(())
+}
+
+fn exec_hook(hook: &Path, timeout: Duration) -> Result<()> {
+    let output = run_hook(&mut Command::new(hook), timeout)?;
+    for line in output.split(|&byte| byte == b'\n') {
+        match line.iter().position(|&byte| byte == b'=') {
+            Some(pos) if is_var_name(&line[..pos]) =>
+                env::set_var(OsStr::from_bytes(&line[..pos]), OsStr::from_bytes(&line[pos + 1..])),
+            _ if line.is_empty() => {}
+            _ => eprintln!("Hook {}: ignoring line: {}", hook.display(), String::from_utf8_lossy(line))
+        }
+    }
+    Ok(())
+}
+
+pub fn run_hooks(sharun_dir: &str, bin_name: &str) -> Result<()> {
+    if get_env_var("SHARUN_NO_HOOKS") == "1" {
+        return Ok(())
+    }
+    let hooks_dir = Path::new(sharun_dir).join(".hooks");
+    let mut hooks = get_hooks(&hooks_dir);
+    hooks.append(&mut get_hooks(&hooks_dir.join(bin_name)));
+    if hooks.is_empty() {
+        return Ok(())
+    }
+
+    let timeout = match get_env_var("SHARUN_HOOKS_TIMEOUT").as_str() {
+        "" => DEFAULT_TIMEOUT,
+        timeout => timeout.parse().map_err(|_| Error::new(InvalidInput,
+            format!("invalid SHARUN_HOOKS_TIMEOUT: '{timeout}', expected seconds")))?
+    };
+    let timeout = Duration::from_secs(timeout);
+    let mut shell: Option<PathBuf> = None;
+    for hook in hooks {
+        let result = if hook.extension().is_some_and(|ext| ext == "sh") {
+            if shell.is_none() {
+                shell = get_shell(sharun_dir)
+            }
+            match &shell {
+                Some(shell) => source_hook(shell, &hook, timeout),
+                None => Err(Error::new(Other, "no shell found"))
+            }
+        } else {
+            exec_hook(&hook, timeout)
+        };
+        // a broken hook should not prevent the launch
+        if let Err(err) = result {
+            eprintln!("Hook {} is skipped: {err}", hook.display())
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A="it's "q" $x `b` \ <newline>nl<tab>tab" B="" C exported but unset, E="x<esc>y<soh>z<del>"
+    const A: &[u8] = b"it's \"q\" $x `b` \\ \nnl\ttab";
+    const E: &[u8] = b"x\x1by\x01z\x7f";
+
+    fn parse(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        parse_exports(data).unwrap().into_iter()
+            .map(|(key, val)| (key.into_string().unwrap(), val.into_vec()))
+            .collect()
+    }
+
+    fn vars(vars: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        vars.iter().map(|(key, val)| (key.to_string(), val.to_vec())).collect()
+    }
+
+    #[test]
+    fn dash() {
+        let data = b"export A='it'\"'\"'s \"q\" $x `b` \\ \nnl\ttab'\nexport B=''\nexport C\n\
+            export E='x\x1by\x01z\x7f'\nexport PWD='/tmp'\nexport U='\xc3\xbcn\xc3\xaf'\n";
+        assert_eq!(parse(data), vars(&[("A", A), ("B", b""), ("E", E), ("PWD", b"/tmp"),
+            ("U", "ünï".as_bytes())]));
+    }
+
+    #[test]
+    fn bash() {
+        let data = b"declare -x A=$'it\\'s \"q\" $x `b` \\\\ \\nnl\\ttab'\ndeclare -x B=\"\"\ndeclare -x C\n\
+            declare -x D=\"a\\\"b\\$c\\\\d\\`e\"\ndeclare -x E=$'x\\Ey\\001z\\177'\ndeclare -rx R=\"1\"\n\
+            declare -x U=$'\\303\\274n\\303\\257'\ndeclare -x V=\"\xc3\xbcn\xc3\xaf\"\n";
+        assert_eq!(parse(data), vars(&[("A", A), ("B", b""), ("D", b"a\"b$c\\d`e"), ("E", E),
+            ("R", b"1"), ("U", "ünï".as_bytes()), ("V", "ünï".as_bytes())]));
+    }
+
+    #[test]
+    fn bash_posix() {
+        let data = b"export A=$'it\\'s \"q\" $x `b` \\\\ \\nnl\\ttab'\nexport B=\"\"\nexport C\n\
+            export PWD=\"/tmp\"\n";
+        assert_eq!(parse(data), vars(&[("A", A), ("B", b""), ("PWD", b"/tmp")]));
+    }
+
+    #[test]
+    fn quoting() {
+        // backslashes outside of quotes, a line continuation in double quotes, hex and short octal
+        let data = b"export A='it'\\''s'\nexport B=\"a\\\nb\"\nexport C=$'\\x41\\x4g\\0\\a\\e'\nexport D=a\\ b\n";
+        assert_eq!(parse(data), vars(&[("A", b"it's"), ("B", b"ab"), ("C", b"A\x04g\0\x07\x1b"), ("D", b"a b")]));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_exports(b"export A='unterminated\n").is_none());
+        assert!(parse_exports(b"export A=\"unterminated\n").is_none());
+        assert!(parse_exports(b"export A=$'unterminated\n").is_none());
+        assert!(parse_exports(b"A=1\n").is_none());
+        assert_eq!(parse(b""), vars(&[]));
+    }
+}
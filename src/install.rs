@@ -0,0 +1,352 @@
+// Desktop integration of the bundle for the current user: symlinks in ~/.local/bin,
+// desktop files and icons in $XDG_DATA_HOME. Everything created is written to a manifest,
+// with the hash of the copied files, so uninstall removes exactly that if it is unchanged.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    collections::{HashMap, HashSet},
+    process::{Command, Stdio},
+    fs::{self, read_to_string},
+    os::unix::fs::symlink,
+    io::{Result, Error, ErrorKind::{InvalidInput, NotFound}}
+};
+
+use walkdir::WalkDir;
+
+use crate::{basename, is_exe, which, desktop::{unescape_value, escape_value, split_exec, quote_exec_arg}};
+
+
+const ICON_EXTENSIONS: [&str; 4] = ["png", "svg", "svgz", "xpm"];
+
+struct Manifest {
+    path: PathBuf,
+    installed: HashSet<PathBuf>,
+    entries: Vec<PathBuf>,
+    // of the copied files, the dirs and symlinks have none
+    hashes: HashMap<PathBuf, String>
+}
+
+// FNV-1a, it only has to notice the changes made by the user and stay the same across versions
+fn hash_data(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3)
+    }
+    format!("{hash:016x}")
+}
+
+fn is_unchanged(path: &Path, hash: &str) -> bool {
+    !path.is_symlink() && fs::read(path).is_ok_and(|data| hash_data(&data) == hash)
+}
+
+impl Manifest {
+    fn open(sharun_dir: &str) -> Result<Self> {
+        // the file name is the escaped bundle path, so it stays the same across sharun versions
+        let name = sharun_dir.replace('%', "%25").replace('/', "%2F");
+        let path = get_data_home()?.join("sharun/installed").join(name);
+        let data = match read_to_string(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == NotFound => String::new(),
+            Err(err) => return Err(err)
+        };
+        // '<path>' or '<path>\t<hash>'
+        let mut entries = Vec::new();
+        let mut hashes = HashMap::new();
+        for line in data.lines().filter(|line| !line.is_empty()) {
+            match line.rsplit_once('\t') {
+                Some((entry, hash)) => {
+                    hashes.insert(PathBuf::from(entry), hash.to_string());
+                    entries.push(PathBuf::from(entry))
+                }
+                None => entries.push(PathBuf::from(line))
+            }
+        }
+        Ok(Self { path, installed: entries.iter().cloned().collect(), entries, hashes })
+    }
+
+    fn add(&mut self, path: &Path) {
+        if self.installed.insert(path.into()) {
+            self.entries.push(path.into())
+        }
+    }
+
+    fn add_file(&mut self, path: &Path, data: &[u8]) {
+        self.add(path);
+        self.hashes.insert(path.into(), hash_data(data));
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(err) if err.kind() != NotFound => Err(err),
+                _ => Ok(())
+            }
+        }
+        let mut data = String::new();
+        for entry in &self.entries {
+            data.push_str(&entry.to_string_lossy());
+            if let Some(hash) = self.hashes.get(entry) {
+                data.push('\t');
+                data.push_str(hash)
+            }
+            data.push('\n')
+        }
+        create_dirs(self.path.parent().unwrap_or(Path::new("/")), None)?;
+        fs::write(&self.path, data)
+    }
+
+    // an existing path is replaced only if it was installed by us and is not changed since
+    fn can_write(&self, path: &Path) -> bool {
+        if path.symlink_metadata().is_err() {
+            return true
+        }
+        if !self.installed.contains(path) {
+            eprintln!("Skipping {}: it already exists", path.display());
+            return false
+        }
+        if self.hashes.get(path).is_some_and(|hash| !is_unchanged(path, hash)) {
+            eprintln!("Skipping {}: it was modified", path.display());
+            return false
+        }
+        true
+    }
+}
+
+fn get_home() -> Result<PathBuf> {
+    match env::var("HOME") {
+        Ok(home) if home.starts_with('/') => Ok(PathBuf::from(home)),
+        _ => Err(Error::new(NotFound, "HOME is not set"))
+    }
+}
+
+fn get_data_home() -> Result<PathBuf> {
+    match env::var("XDG_DATA_HOME") {
+        Ok(data_home) if data_home.starts_with('/') => Ok(PathBuf::from(data_home)),
+        _ => Ok(get_home()?.join(".local/share"))
+    }
+}
+
+fn create_dirs(dir: &Path, mut manifest: Option<&mut Manifest>) -> Result<()> {
+    let mut new_dirs = Vec::new();
+    let mut parent = Some(dir);
+    while let Some(dir) = parent {
+        if dir.exists() {
+            break
+        }
+        new_dirs.push(dir);
+        parent = dir.parent()
+    }
+    for dir in new_dirs.into_iter().rev() {
+        fs::create_dir(dir)?;
+        if let Some(manifest) = manifest.as_mut() {
+            manifest.add(dir)
+        }
+    }
+    Ok(())
+}
+
+fn install_file(data: &[u8], dest: &Path, manifest: &mut Manifest) -> Result<()> {
+    if !manifest.can_write(dest) {
+        return Ok(())
+    }
+    create_dirs(dest.parent().unwrap_or(Path::new("/")), Some(manifest))?;
+    if dest.is_symlink() {
+        fs::remove_file(dest)?
+    }
+    fs::write(dest, data)?;
+    manifest.add_file(dest, data);
+    Ok(())
+}
+
+fn install_symlink(target: &Path, dest: &Path, manifest: &mut Manifest) -> Result<()> {
+    if fs::read_link(dest).is_ok_and(|link| link == target) {
+        manifest.add(dest);
+        return Ok(())
+    }
+    if !manifest.can_write(dest) {
+        return Ok(())
+    }
+    create_dirs(dest.parent().unwrap_or(Path::new("/")), Some(manifest))?;
+    if dest.symlink_metadata().is_ok() {
+        fs::remove_file(dest)?
+    }
+    symlink(target, dest)?;
+    manifest.add(dest);
+    Ok(())
+}
+
+fn get_bundle_bins(bin_dir: &str) -> Vec<String> {
+    let mut bins: Vec<String> = Path::new(bin_dir).read_dir().map(|dir| dir.flatten()
+        .filter(|entry| is_exe(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()).unwrap_or_default();
+    bins.sort();
+    bins
+}
+
+struct DesktopFile {
+    data: String,
+    bin: Option<String>,
+    icons: Vec<String>
+}
+
+// points Exec and TryExec of all groups to the launchers in the bundle 'bin' dir
+fn rewrite_desktop_file(data: &str, bin_dir: &str, bundle_bins: &[String]) -> Result<DesktopFile> {
+    let launcher = |program: &str| {
+        let name = basename(program);
+        bundle_bins.contains(&name).then(|| format!("{bin_dir}/{name}"))
+    };
+    let mut desktop_file = DesktopFile { data: String::new(), bin: None, icons: Vec::new() };
+    let mut group = "";
+    for line in data.lines() {
+        let trimmed = line.trim();
+        let mut rewritten = None;
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            group = trimmed
+        } else if let Some((key, value)) = trimmed.split_once('=') {
+            let value = unescape_value(value.trim());
+            match key.trim() {
+                "Exec" => {
+                    let mut args = split_exec(&value)?;
+                    if let Some(program) = args.first_mut() {
+                        if group == "[Desktop Entry]" && desktop_file.bin.is_none() {
+                            desktop_file.bin = Some(basename(program))
+                        }
+                        if let Some(launcher) = launcher(program) {
+                            *program = launcher;
+                            let exec: Vec<String> = args.iter().map(|arg| quote_exec_arg(arg)).collect();
+                            rewritten = Some(format!("Exec={}", escape_value(&exec.join(" "))))
+                        }
+                    }
+                }
+                "TryExec" => if let Some(launcher) = launcher(&value) {
+                    rewritten = Some(format!("TryExec={}", escape_value(&launcher)))
+                }
+                "Icon" if !value.is_empty() && !value.contains('/') => desktop_file.icons.push(value),
+                _ => {}
+            }
+        }
+        desktop_file.data.push_str(rewritten.as_deref().unwrap_or(line));
+        desktop_file.data.push('\n')
+    }
+    Ok(desktop_file)
+}
+
+fn update_desktop_database(applications_dir: &Path) {
+    if let Some(update_desktop_database) = which("update-desktop-database") {
+        let _ = Command::new(update_desktop_database)
+            .arg("-q").arg(applications_dir)
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .status();
+    }
+}
+
+fn install_all(sharun_dir: &str, bins: &[String], manifest: &mut Manifest) -> Result<()> {
+    let bin_dir = &format!("{sharun_dir}/bin");
+    let bundle_bins = get_bundle_bins(bin_dir);
+    for bin in bins {
+        if !bundle_bins.contains(bin) {
+            return Err(Error::new(NotFound, format!("'{bin}' is not found in '{bin_dir}'")))
+        }
+    }
+    let bins = if bins.is_empty() { &bundle_bins } else { bins };
+
+    let bin_home = get_home()?.join(".local/bin");
+    for bin in bins {
+        install_symlink(&Path::new(bin_dir).join(bin), &bin_home.join(bin), manifest)?
+    }
+
+    let data_home = get_data_home()?;
+    let mut desktop_paths: Vec<PathBuf> = Path::new(sharun_dir).join("share/applications").read_dir()
+        .map(|dir| dir.flatten().map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "desktop"))
+            .collect()).unwrap_or_default();
+    desktop_paths.sort();
+    let mut icons = HashSet::new();
+    for desktop_path in desktop_paths {
+        let data = read_to_string(&desktop_path)?;
+        let desktop_file = rewrite_desktop_file(&data, bin_dir, &bundle_bins).map_err(|err|
+            Error::new(err.kind(), format!("{}: {err}", desktop_path.display())))?;
+        if desktop_file.bin.as_ref().is_none_or(|bin| !bins.contains(bin)) {
+            continue
+        }
+        let dest = data_home.join("applications").join(desktop_path.file_name().unwrap_or_default());
+        install_file(desktop_file.data.as_bytes(), &dest, manifest)?;
+        icons.extend(desktop_file.icons)
+    }
+
+    let is_icon = |path: &Path| path.is_file() &&
+        path.extension().is_some_and(|ext| ICON_EXTENSIONS.iter().any(|icon_ext| ext == *icon_ext)) &&
+        path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| icons.contains(stem));
+    let icons_dir = Path::new(sharun_dir).join("share/icons");
+    for entry in WalkDir::new(&icons_dir).sort_by_file_name().into_iter().flatten() {
+        let path = entry.path();
+        if is_icon(path) {
+            let dest = data_home.join("icons").join(path.strip_prefix(&icons_dir).unwrap_or(path));
+            install_file(&fs::read(path)?, &dest, manifest)?
+        }
+    }
+    // icons from pixmaps are found by the themes in the base icons dir
+    let pixmaps_dir = Path::new(sharun_dir).join("share/pixmaps");
+    for entry in WalkDir::new(&pixmaps_dir).max_depth(1).sort_by_file_name().into_iter().flatten() {
+        let path = entry.path();
+        if is_icon(path) {
+            let dest = data_home.join("icons").join(entry.file_name());
+            install_file(&fs::read(path)?, &dest, manifest)?
+        }
+    }
+
+    update_desktop_database(&data_home.join("applications"));
+    Ok(())
+}
+
+pub fn install(sharun_dir: &str, bins: &[String]) -> Result<()> {
+    let mut manifest = Manifest::open(sharun_dir)?;
+    let result = install_all(sharun_dir, bins, &mut manifest);
+    manifest.save()?;
+    for entry in &manifest.entries {
+        println!("{}", entry.display())
+    }
+    result
+}
+
+pub fn uninstall(sharun_dir: &str) -> Result<()> {
+    let mut manifest = Manifest::open(sharun_dir)?;
+    if manifest.entries.is_empty() {
+        return Err(Error::new(InvalidInput, format!("nothing is installed from '{sharun_dir}'")))
+    }
+    let mut kept = Vec::new();
+    for entry in manifest.entries.iter().rev() {
+        let hash = manifest.hashes.get(entry);
+        let result = match entry.symlink_metadata() {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir(entry),
+            // the file or the symlink was changed or replaced by someone else
+            Ok(_) if hash.is_some_and(|hash| !is_unchanged(entry, hash)) => {
+                eprintln!("Skipping {}: it was modified", entry.display());
+                continue
+            }
+            Ok(_) if hash.is_none() && !fs::read_link(entry)
+                .is_ok_and(|link| link.starts_with(sharun_dir)) => {
+                eprintln!("Skipping {}: it was replaced", entry.display());
+                continue
+            }
+            Ok(_) => fs::remove_file(entry),
+            Err(_) => Ok(())
+        };
+        match result {
+            Ok(()) => println!("{}", entry.display()),
+            // a dir that the user has put other files in
+            Err(_) if entry.is_dir() => {}
+            Err(err) => {
+                eprintln!("Failed to remove {}: {err}", entry.display());
+                kept.insert(0, entry.clone())
+            }
+        }
+    }
+    manifest.entries = kept;
+    manifest.save()?;
+    update_desktop_database(&get_data_home()?.join("applications"));
+    Ok(())
+}
@@ -1,25 +1,89 @@
 use std::{
     env,
-    str::FromStr,
     path::{Path, PathBuf},
-    ffi::{CString, OsStr},
+    collections::HashSet,
+    ffi::{CStr, CString, OsStr, OsString},
     process::{Command, exit},
-    fs::{File, write, read_to_string},
-    os::unix::{fs::{MetadataExt, PermissionsExt}, process::CommandExt},
-    io::{Read, Result, Error, Write, BufRead, BufReader, ErrorKind::{InvalidData, NotFound}}
+    fs::{File, read, write, read_to_string},
+    convert::Infallible,
+    os::{fd::AsRawFd, unix::{ffi::OsStrExt, fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt}, process::CommandExt}},
+    io::{Read, Seek, SeekFrom, Result, Error, BufRead, BufReader, ErrorKind::{InvalidData, InvalidInput, NotFound, UnexpectedEof}}
 };
+#[cfg(feature = "lib4bin")]
+use std::io::Write;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod userland_exec;
+mod desktop;
+mod hooks;
+mod install;
+mod collect;
+mod manifest;
+#[cfg(feature = "sign")]
+mod sign;
+#[cfg(feature = "pack")]
+mod pack;
+#[cfg(feature = "trace")]
+mod trace;
 
 use cfg_if::cfg_if;
 use walkdir::WalkDir;
-use nix::unistd::{access, AccessFlags};
-use goblin::elf::{Elf, program_header::PT_INTERP};
+use nix::{
+    libc,
+    unistd::{access, fexecve, AccessFlags},
+    sys::prctl::set_name,
+    sys::memfd::{memfd_create, MFdFlags}
+};
+use goblin::{container::Ctx, strtab::Strtab, elf::{Elf, SectionHeader, dynamic::{Dynamic, DT_NEEDED, DT_RPATH, DT_RUNPATH},
+    program_header::{ProgramHeader, PT_DYNAMIC, PT_INTERP}}};
+use goblin::elf::header::{EI_CLASS, EI_DATA, ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB,
+    EM_386, EM_AARCH64, EM_ARM, EM_PPC64, EM_RISCV, EM_S390, EM_X86_64};
 
 
 const SHARUN_NAME: &str = env!("CARGO_PKG_NAME");
+const EM_LOONGARCH: u16 = 258;
+
+cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const MULTIARCH: &str = "x86_64-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_X86_64, EM_386];
+        const MULTIARCH32: &str = "i386-linux-gnu";
+    } else if #[cfg(target_arch = "x86")] {
+        const MULTIARCH: &str = "i386-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_386];
+    } else if #[cfg(target_arch = "aarch64")] {
+        const MULTIARCH: &str = "aarch64-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_AARCH64, EM_ARM];
+        const MULTIARCH32: &str = "arm-linux-gnueabihf";
+    } else if #[cfg(target_arch = "arm")] {
+        const MULTIARCH: &str = "arm-linux-gnueabihf";
+        const ELF_MACHINES: &[u16] = &[EM_ARM];
+    } else if #[cfg(target_arch = "riscv64")] {
+        const MULTIARCH: &str = "riscv64-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_RISCV];
+    } else if #[cfg(all(target_arch = "powerpc64", target_endian = "little"))] {
+        const MULTIARCH: &str = "powerpc64le-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_PPC64];
+    } else if #[cfg(target_arch = "s390x")] {
+        const MULTIARCH: &str = "s390x-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_S390];
+    } else if #[cfg(target_arch = "loongarch64")] {
+        const MULTIARCH: &str = "loongarch64-linux-gnu";
+        const ELF_MACHINES: &[u16] = &[EM_LOONGARCH];
+    } else {
+        compile_error!("Unsupported target architecture!");
+    }
+}
 
 
-fn get_interpreter(library_path: &str) -> Result<PathBuf> {
+fn get_interpreter(library_path: &str, interp: Option<&str>) -> Result<PathBuf> {
     let mut interpreters = Vec::new();
+    if let Some(interp) = interp {
+        let ldname = basename(interp);
+        if !ldname.is_empty() {
+            interpreters.push(ldname)
+        }
+    }
     if let Ok(ldname) = env::var("SHARUN_LDNAME") {
         if !ldname.is_empty() {
             interpreters.push(ldname)
@@ -29,12 +93,48 @@ fn get_interpreter(library_path: &str) -> Result<PathBuf> {
         interpreters.append(&mut vec![
             "ld-linux-x86-64.so.2".into(),
             "ld-musl-x86_64.so.1".into(),
-            "ld-linux.so.2".into()
+            "ld-linux.so.2".into(),
+            "ld-musl-i386.so.1".into()
+        ]);
+        #[cfg(target_arch = "x86")]             // target i686-unknown-linux-musl
+        interpreters.append(&mut vec![
+            "ld-linux.so.2".into(),
+            "ld-musl-i386.so.1".into()
         ]);
         #[cfg(target_arch = "aarch64")]         // target aarch64-unknown-linux-musl
         interpreters.append(&mut vec![
             "ld-linux-aarch64.so.1".into(),
-            "ld-musl-aarch64.so.1".into()
+            "ld-musl-aarch64.so.1".into(),
+            "ld-linux-armhf.so.3".into(),
+            "ld-musl-armhf.so.1".into()
+        ]);
+        #[cfg(target_arch = "arm")]             // target armv7-unknown-linux-musleabihf
+        interpreters.append(&mut vec![
+            "ld-linux-armhf.so.3".into(),
+            "ld-musl-armhf.so.1".into(),
+            "ld-linux.so.3".into(),
+            "ld-musl-arm.so.1".into()
+        ]);
+        #[cfg(target_arch = "riscv64")]         // target riscv64gc-unknown-linux-musl
+        interpreters.append(&mut vec![
+            "ld-linux-riscv64-lp64d.so.1".into(),
+            "ld-musl-riscv64.so.1".into(),
+            "ld-linux-riscv64-lp64.so.1".into()
+        ]);
+        #[cfg(target_arch = "powerpc64")]       // target powerpc64le-unknown-linux-musl
+        interpreters.append(&mut vec![
+            "ld64.so.2".into(),
+            "ld-musl-powerpc64le.so.1".into()
+        ]);
+        #[cfg(target_arch = "s390x")]           // target s390x-unknown-linux-musl
+        interpreters.append(&mut vec![
+            "ld64.so.1".into(),
+            "ld-musl-s390x.so.1".into()
+        ]);
+        #[cfg(target_arch = "loongarch64")]     // target loongarch64-unknown-linux-musl
+        interpreters.append(&mut vec![
+            "ld-linux-loongarch-lp64d.so.1".into(),
+            "ld-musl-loongarch64.so.1".into()
         ]);
     }
     for interpreter in interpreters {
@@ -46,6 +146,281 @@ fn get_interpreter(library_path: &str) -> Result<PathBuf> {
     Err(Error::last_os_error())
 }
 
+struct Loader {
+    argv0: bool,
+    preload: bool,
+    inhibit_cache: bool,
+    #[cfg(feature = "audit")]
+    audit: bool,
+    is_glibc: bool
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+// only the section with the strings, the loader is read on every launch
+fn read_elf_section(path: &Path, section_name: &str) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut header_bytes = [0; 64];
+    file.read_exact(&mut header_bytes)?;
+    let header = Elf::parse_header(&header_bytes)
+        .map_err(|err| Error::new(InvalidData, err))?;
+    let ctx = Ctx::new(
+        header.container().map_err(|err| Error::new(InvalidData, err))?,
+        header.endianness().map_err(|err| Error::new(InvalidData, err))?
+    );
+    let mut section_headers_bytes = vec![0; header.e_shnum as usize * header.e_shentsize as usize];
+    file.seek(SeekFrom::Start(header.e_shoff))?;
+    file.read_exact(&mut section_headers_bytes)?;
+    let section_headers = SectionHeader::parse_from(&section_headers_bytes, 0, header.e_shnum as usize, ctx)
+        .map_err(|err| Error::new(InvalidData, err))?;
+    let read_section = |file: &mut File, section_header: &SectionHeader| -> Result<Vec<u8>> {
+        let mut section_bytes = vec![0; section_header.sh_size as usize];
+        file.seek(SeekFrom::Start(section_header.sh_offset))?;
+        file.read_exact(&mut section_bytes)?;
+        Ok(section_bytes)
+    };
+    let shstrtab = section_headers.get(header.e_shstrndx as usize)
+        .ok_or_else(|| Error::new(InvalidData, "no section names"))?;
+    let shstrtab = read_section(&mut file, shstrtab)?;
+    let section_header = section_headers.iter().find(|section_header| {
+        shstrtab.get(section_header.sh_name..).unwrap_or_default()
+            .split(|byte| *byte == 0).next() == Some(section_name.as_bytes())
+    }).ok_or_else(|| Error::new(InvalidData, format!("no {section_name} section")))?;
+    read_section(&mut file, section_header)
+}
+
+fn get_loader(interpreter: &Path) -> Result<Loader> {
+    let rodata = read_elf_section(interpreter, ".rodata")?;
+    let name = interpreter.file_name().unwrap_or_default().to_string_lossy();
+    let is_glibc = name.starts_with("ld-linux") || name.starts_with("ld64.so") ||
+        contains_bytes(&rodata, b"GLIBC_PRIVATE");
+    let is_musl = name.starts_with("ld-musl-") || contains_bytes(&rodata, b"musl libc");
+    // glibc compares the whole options, musl only what follows the dashes
+    let has_option = |option: &str| if is_glibc {
+        contains_bytes(&rodata, format!("--{option}\0").as_bytes())
+    } else {
+        contains_bytes(&rodata, format!("{option}\0").as_bytes())
+    };
+    if !is_glibc && !is_musl {
+        return Err(Error::new(InvalidData, "not a glibc or musl dynamic loader"))
+    }
+    // all of them know --library-path, without it the probing is wrong
+    if !has_option("library-path") {
+        return Err(Error::new(InvalidData, "the loader does not support --library-path"))
+    }
+    // old glibc (< 2.33) and musl loaders lack some of the options, LD_PRELOAD can replace --preload
+    Ok(Loader {
+        argv0: has_option("argv0"),
+        preload: has_option("preload"),
+        inhibit_cache: is_glibc && has_option("inhibit-cache"),
+        #[cfg(feature = "audit")]
+        audit: is_glibc && has_option("audit"),
+        is_glibc
+    })
+}
+
+fn get_missing_libs(bin: &str, library_path: &str) -> Vec<String> {
+    let mut missing_libs = Vec::new();
+    let mut seen_libs = HashSet::new();
+    let mut elfs = vec![PathBuf::from(bin)];
+    while let Some(elf_path) = elfs.pop() {
+        let Ok((elf_info, entries)) = read_elf_dynamic(&elf_path) else { continue };
+        // the loader skips the libraries of another class or machine
+        let is_compatible = |path: &Path| get_elf_info(&path.to_string_lossy())
+            .is_ok_and(|info| info.is_elf32 == elf_info.is_elf32 && info.machine == elf_info.machine);
+        let origin = elf_path.parent().unwrap_or(Path::new("/")).to_string_lossy().to_string();
+        let mut lib_dirs: Vec<String> = entries.iter()
+            .filter(|(tag, _)| *tag != DT_NEEDED)
+            .flat_map(|(_, path)| path.split(":"))
+            .map(|dir| dir.replace("${ORIGIN}", &origin).replace("$ORIGIN", &origin))
+            .collect();
+        lib_dirs.extend(library_path.split(":").filter(|dir| !dir.is_empty()).map(String::from));
+        for (_, lib) in entries.iter().filter(|(tag, _)| *tag == DT_NEEDED) {
+            if !seen_libs.insert(lib.to_string()) {
+                continue
+            }
+            let lib_path = if lib.contains("/") {
+                Some(PathBuf::from(lib)).filter(|path| is_compatible(path))
+            } else {
+                lib_dirs.iter().map(|dir| Path::new(dir).join(lib)).find(|path| is_compatible(path))
+            };
+            match lib_path {
+                Some(lib_path) => elfs.push(lib_path),
+                None => missing_libs.push(lib.to_string())
+            }
+        }
+    }
+    missing_libs
+}
+
+struct ElfInfo {
+    is_elf32: bool,
+    is_le: bool,
+    machine: u16,
+    interp: Option<String>
+}
+
+fn get_elf_info(path: &str) -> Result<ElfInfo> {
+    read_elf_headers(&mut File::open(path)?).map(|(elf_info, _, _)| elf_info)
+}
+
+fn read_elf_headers(file: &mut File) -> Result<(ElfInfo, Ctx, Vec<ProgramHeader>)> {
+    let mut header_bytes = [0; 64];
+    let header_len = file.read(&mut header_bytes)?;
+    if header_len < 4 || &header_bytes[0..4] != b"\x7fELF" {
+        if header_bytes.starts_with(b"#!") {
+            return Err(Error::new(InvalidData, "it is a script, not an ELF binary"))
+        }
+        return Err(Error::new(InvalidData, "it is not an ELF binary"))
+    }
+    if header_len < header_bytes.len() {
+        return Err(Error::new(InvalidData, "the ELF header is truncated"))
+    }
+    if ![ELFCLASS32, ELFCLASS64].contains(&header_bytes[EI_CLASS]) {
+        return Err(Error::new(InvalidData, format!("invalid ELF class: {}", header_bytes[EI_CLASS])))
+    }
+    if ![ELFDATA2LSB, ELFDATA2MSB].contains(&header_bytes[EI_DATA]) {
+        return Err(Error::new(InvalidData, format!("invalid ELF data encoding: {}", header_bytes[EI_DATA])))
+    }
+    let header = Elf::parse_header(&header_bytes)
+        .map_err(|err| Error::new(InvalidData, err))?;
+    let ctx = Ctx::new(
+        header.container().map_err(|err| Error::new(InvalidData, err))?,
+        header.endianness().map_err(|err| Error::new(InvalidData, err))?
+    );
+    let mut program_headers_bytes = vec![0; header.e_phnum as usize * header.e_phentsize as usize];
+    file.seek(SeekFrom::Start(header.e_phoff))?;
+    file.read_exact(&mut program_headers_bytes).map_err(truncated)?;
+    let program_headers = ProgramHeader::parse(&program_headers_bytes, 0, header.e_phnum as usize, ctx)
+        .map_err(|err| Error::new(InvalidData, err))?;
+    let mut interp = None;
+    if let Some(interp_header) = program_headers.iter().find(|header| header.p_type == PT_INTERP) {
+        let mut interp_bytes = vec![0; interp_header.p_filesz as usize];
+        file.seek(SeekFrom::Start(interp_header.p_offset))?;
+        file.read_exact(&mut interp_bytes).map_err(truncated)?;
+        let interp_bytes = interp_bytes.split(|byte| *byte == 0).next().unwrap_or_default();
+        interp = Some(String::from_utf8_lossy(interp_bytes).into())
+    }
+    let elf_info = ElfInfo {
+        is_elf32: header.e_ident[EI_CLASS] == ELFCLASS32,
+        is_le: header.e_ident[EI_DATA] == ELFDATA2LSB,
+        machine: header.e_machine,
+        interp
+    };
+    Ok((elf_info, ctx, program_headers))
+}
+
+fn truncated(err: Error) -> Error {
+    if err.kind() == UnexpectedEof {
+        Error::new(InvalidData, "the ELF file is truncated")
+    } else { err }
+}
+
+// the DT_NEEDED, DT_RPATH and DT_RUNPATH strings, read without the rest of the file
+fn read_elf_dynamic(path: &Path) -> Result<(ElfInfo, Vec<(u64, String)>)> {
+    let mut file = File::open(path)?;
+    let (elf_info, ctx, mut program_headers) = read_elf_headers(&mut file)?;
+    let Some(dynamic_header) = program_headers.iter_mut().find(|header| header.p_type == PT_DYNAMIC) else {
+        return Ok((elf_info, Vec::new()))
+    };
+    let mut dynamic_bytes = vec![0; dynamic_header.p_filesz as usize];
+    file.seek(SeekFrom::Start(dynamic_header.p_offset))?;
+    file.read_exact(&mut dynamic_bytes).map_err(truncated)?;
+    // the segment is parsed from its own bytes
+    dynamic_header.p_offset = 0;
+    let Some(dynamic) = Dynamic::parse(&dynamic_bytes, &program_headers, ctx)
+        .map_err(|err| Error::new(InvalidData, err))? else {
+        return Ok((elf_info, Vec::new()))
+    };
+    let mut strtab_bytes = vec![0; dynamic.info.strsz];
+    file.seek(SeekFrom::Start(dynamic.info.strtab as u64))?;
+    file.read_exact(&mut strtab_bytes).map_err(truncated)?;
+    let strtab = Strtab::parse(&strtab_bytes, 0, strtab_bytes.len(), 0)
+        .map_err(|err| Error::new(InvalidData, err))?;
+    let entries = dynamic.dyns.iter()
+        .filter(|entry| [DT_NEEDED, DT_RPATH, DT_RUNPATH].contains(&entry.d_tag))
+        .filter_map(|entry| strtab.get_at(entry.d_val as usize).map(|string| (entry.d_tag, string.to_string())))
+        .collect();
+    Ok((elf_info, entries))
+}
+
+fn get_elf_arch(elf_info: &ElfInfo) -> Option<&'static str> {
+    match elf_info.machine {
+        EM_X86_64 => Some("x86_64"),
+        EM_386 => Some("i386"),
+        EM_AARCH64 => Some("aarch64"),
+        EM_ARM => Some("arm"),
+        EM_RISCV if elf_info.is_elf32 => Some("riscv32"),
+        EM_RISCV => Some("riscv64"),
+        EM_PPC64 if elf_info.is_le => Some("ppc64le"),
+        EM_PPC64 => Some("ppc64"),
+        EM_S390 => Some("s390x"),
+        EM_LOONGARCH => Some("loongarch64"),
+        _ => None
+    }
+}
+
+fn describe_elf(elf_info: &ElfInfo) -> String {
+    let arch = get_elf_arch(elf_info)
+        .map(String::from)
+        .unwrap_or_else(|| format!("machine {}", elf_info.machine));
+    format!("{arch} {}-bit {} endian ELF",
+        if elf_info.is_elf32 { 32 } else { 64 },
+        if elf_info.is_le { "little" } else { "big" }
+    )
+}
+
+fn is_native_elf(elf_info: &ElfInfo) -> bool {
+    ELF_MACHINES.contains(&elf_info.machine) && elf_info.is_le == cfg!(target_endian = "little")
+}
+
+fn get_emulator(shared_bin: &str, qemu_arch: &str) -> Option<PathBuf> {
+    let mut emulators = Vec::new();
+    let emulator = get_env_var("SHARUN_EMULATOR");
+    if !emulator.is_empty() {
+        if emulator.contains('/') {
+            return is_exe(Path::new(&emulator)).then(|| emulator.into())
+        }
+        emulators.push(emulator)
+    } else {
+        emulators.push(format!("qemu-{qemu_arch}"));
+        emulators.push(format!("qemu-{qemu_arch}-static"))
+    }
+    for emulator in &emulators {
+        let emulator_path = Path::new(shared_bin).join(emulator);
+        if is_exe(&emulator_path) {
+            return Some(emulator_path)
+        }
+    }
+    emulators.iter().find_map(which)
+}
+
+fn exec_emulator(emulator: &Path, shared_dir: &str, library_path: &str,
+    interpreter_args: &[OsString], bin: &str, exec_args: &[OsString]) -> Error {
+    let emulator_name = emulator.file_name().unwrap_or_default().to_string_lossy();
+    let mut command = Command::new(emulator);
+    if emulator_name.starts_with("qemu-") {
+        // keep the loader env away from a dynamically linked host qemu
+        command.arg("-L").arg(shared_dir);
+        for var_name in ["LD_LIBRARY_PATH", "LD_PRELOAD"] {
+            if let Ok(value) = env::var(var_name) {
+                command.env_remove(var_name).arg("-E").arg(format!("{var_name}={value}"));
+            }
+        }
+        command.args(interpreter_args);
+    } else if emulator_name.starts_with("box") {
+        // box64 and box86 bring their own loader
+        command.env("BOX64_LD_LIBRARY_PATH", library_path)
+            .env("BOX86_LD_LIBRARY_PATH", library_path)
+            .arg(bin).args(exec_args);
+    } else {
+        command.args(interpreter_args);
+    }
+    command.exec()
+}
+
 fn realpath(path: &str) -> String {
     Path::new(path).canonicalize().unwrap_or_default().to_str().unwrap_or_default().to_string()
 }
@@ -55,21 +430,6 @@ fn basename(path: &str) -> String {
     pieces.first().unwrap_or(&"").to_string()
 }
 
-fn dirname(path: &str) -> String {
-    let mut pieces: Vec<&str> = path.split('/').collect();
-    if pieces.len() == 1 || path.is_empty() {
-        // return ".".to_string();
-    } else if !path.starts_with('/') &&
-        !path.starts_with('.') &&
-        !path.starts_with('~') {
-            pieces.insert(0, ".");
-    } else if pieces.len() == 2 && path.starts_with('/') {
-        pieces.insert(0, "");
-    };
-    pieces.pop();
-    pieces.join(&'/'.to_string())
-}
-
 fn is_hardlink(path1: &Path, path2: &Path) -> bool {
     if let Ok(metadata1) = path1.metadata() {
         if let Ok(metadata2) = path2.metadata() {
@@ -98,6 +458,7 @@ fn is_dir(path: &str) -> bool {
     Path::new(path).is_dir()
 }
 
+#[cfg(feature = "setenv")]
 fn is_file(path: &Path) -> bool {
     if let Ok(metadata) = path.metadata() {
         return metadata.is_file()
@@ -112,10 +473,10 @@ fn is_exe(path: &Path) -> bool {
     false
 }
 
-fn which(executable: &str) -> Option<PathBuf> {
+fn which<P: AsRef<Path>>(executable: P) -> Option<PathBuf> {
     if let Ok(path) = env::var("PATH") {
         for dir in path.split(':') {
-            let full_path = Path::new(dir).join(executable);
+            let full_path = Path::new(dir).join(&executable);
             if is_exe(&full_path) {
                 return Some(full_path)
             }
@@ -139,7 +500,7 @@ fn read_first_line(path: &PathBuf) -> Result<String> {
     Ok(line)
 }
 
-fn exec_script(path: &PathBuf, exec_args: &[String]) -> Result<()> {
+fn exec_script(path: &PathBuf, exec_args: &[OsString]) -> Result<()> {
     let first_line = read_first_line(path)?;
     if !first_line.starts_with("#!") {
         return Err(Error::new(NotFound, "Script does not have a valid shebang!"))
@@ -191,17 +552,6 @@ fn exec_script(path: &PathBuf, exec_args: &[String]) -> Result<()> {
     Err(Error::new(InvalidData, err))
 }
 
-#[cfg(feature = "elf32")]
-fn is_elf32(path: &String) -> Result<bool> {
-    let mut file = File::open(path)?;
-    let mut elf_bytes = [0; 5];
-    file.read_exact(&mut elf_bytes)?;
-    if &elf_bytes[0..4] != b"\x7fELF" {
-        return Ok(false)
-    }
-    Ok(elf_bytes[4] == 1)
-}
-
 #[cfg(feature = "pyinstaller")]
 fn get_elf(path: &String, is_elf32: bool) -> Result<Vec<u8>> {
     let mut file = File::open(path)?;
@@ -239,38 +589,255 @@ fn is_elf_section(elf_bytes: &[u8], section_name: &str) -> Result<bool> {
     Ok(false)
 }
 
-fn write_file(elf_path: &String, bytes: &[u8]) -> Result<bool> {
-    let mut file = File::create(elf_path)?;
-    file.write_all(bytes)?;
-    Ok(true)
-}
-
-fn set_interp(mut elf_bytes: Vec<u8>, elf_path: &String, new_interp: &str) -> Result<bool> {
-    let elf = Elf::parse(&elf_bytes)
+fn set_interp(elf_bytes: &[u8], elf_file: &File, new_interp: &str) -> Result<()> {
+    let elf = Elf::parse(elf_bytes)
         .map_err(|err| Error::new(InvalidData, err))?;
     let interp_header = elf.program_headers.iter().find(|header| header.p_type == PT_INTERP);
     match interp_header {
         Some(header) => {
             let start = header.p_offset as usize;
             let end = start + header.p_filesz as usize;
-            let interp_slice = &mut elf_bytes[start..end];
-            if interp_slice.last() != Some(&0) {
+            if elf_bytes.get(end - 1) != Some(&0) {
                 return Err(Error::new(InvalidData, "Current INTERP not NUL terminated"));
             }
             if new_interp.len() > (header.p_filesz as usize) - 1 {
                 return Err(Error::new(InvalidData, "Current INTERP too small"));
             }
-            let new_interp_bytes = new_interp.as_bytes();
-            interp_slice[..new_interp_bytes.len()].copy_from_slice(new_interp_bytes);
-            for byte in interp_slice.iter_mut().take((header.p_filesz as usize) - 1).skip(new_interp_bytes.len()) { *byte = 0 }
-            interp_slice[(header.p_filesz as usize) - 1] = 0;
-            write_file(elf_path, &elf_bytes)?;
+            let mut interp_bytes = vec![0; header.p_filesz as usize];
+            interp_bytes[..new_interp.len()].copy_from_slice(new_interp.as_bytes());
+            elf_file.write_all_at(&interp_bytes, header.p_offset)?;
         }
         None => {
             return Err(Error::new(InvalidData, "Failed to find PT_INTERP header"));
         }
     }
-    Ok(true)
+    Ok(())
+}
+
+// an executable memfd, or an unlinked temp file where memfds are not executable
+fn write_exec_file(name: &str, write_file: impl Fn(&mut File) -> Result<()>) -> Result<File> {
+    // kernels before 6.3 reject MFD_EXEC, and vm.memfd_noexec=2 rejects it with EACCES
+    let mfd_exec = MFdFlags::from_bits_retain(libc::MFD_EXEC);
+    let memfd = match memfd_create(name, MFdFlags::MFD_CLOEXEC | mfd_exec) {
+        Err(nix::errno::Errno::EINVAL) => memfd_create(name, MFdFlags::MFD_CLOEXEC),
+        memfd => memfd
+    };
+    if let Ok(memfd) = memfd {
+        let mut file = File::from(memfd);
+        write_file(&mut file)?;
+        return Ok(file)
+    }
+    let tmp_path = env::temp_dir().join(format!(".{SHARUN_NAME}-{name}.{}", std::process::id()));
+    let result = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o700).open(&tmp_path)
+        .and_then(|mut file| write_file(&mut file))
+        // a file that is still open for writing can not be executed
+        .and_then(|_| File::open(&tmp_path));
+    std::fs::remove_file(&tmp_path).ok();
+    result
+}
+
+fn exec_with_interp(elf_bytes: &[u8], bin: &str, interpreter: &Path, exec_args: &[OsString]) -> Result<Infallible> {
+    // the patched copy is not in the bundle, so the bundle is never modified, and the interpreter
+    // is referenced through an fd whatever its path length, the kernel opens it before closing the fds
+    let interpreter_file = File::open(interpreter)?;
+    let new_interp = if Path::new("/proc/self/fd").is_dir() {
+        format!("/proc/self/fd/{}", interpreter_file.as_raw_fd())
+    } else {
+        interpreter.to_string_lossy().into()
+    };
+    let elf_file = write_exec_file(&basename(bin), |elf_file| {
+        std::io::copy(&mut File::open(bin)?, elf_file)?;
+        set_interp(elf_bytes, elf_file, &new_interp)
+    })?;
+    let mut args = vec![to_cstring(bin)?];
+    args.append(&mut to_cstrings(exec_args)?);
+    Ok(fexecve(&elf_file, &args, &get_envs()?)?)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExecStrategy {
+    // userland, or kernel if the interpreter cannot be mapped
+    Auto,
+    // maps the bundled interpreter into the sharun process, see userland_exec.rs
+    Userland,
+    // executes the bundled interpreter with execve
+    Kernel,
+    // executes the binary itself, dynamic binaries get the host interpreter and LD_LIBRARY_PATH
+    Direct
+}
+
+fn is_pax_mprotect() -> bool {
+    // PaX kernels show the flags in /proc/self/status, 'M' is MPROTECT
+    read_to_string("/proc/self/status").unwrap_or_default().lines()
+        .find_map(|line| line.strip_prefix("PaX:"))
+        .is_some_and(|flags| flags.contains('M'))
+}
+
+fn get_exec_strategy() -> ExecStrategy {
+    match get_env_var("SHARUN_EXEC").as_str() {
+        "" | "auto" => if is_pax_mprotect() {
+            if get_env_var("SHARUN_PRINTENV") == "1" {
+                eprintln!("PaX MPROTECT is enabled, using the kernel exec")
+            }
+            ExecStrategy::Kernel
+        } else {
+            ExecStrategy::Auto
+        },
+        "userland" => ExecStrategy::Userland,
+        "kernel" => ExecStrategy::Kernel,
+        "direct" => ExecStrategy::Direct,
+        exec => {
+            eprintln!("Invalid SHARUN_EXEC: {exec}: expected auto, userland, kernel or direct");
+            exit(1)
+        }
+    }
+}
+
+// /proc/self/exe can not be changed while the sharun image is mapped, PR_SET_MM_EXE_FILE fails with EBUSY
+fn set_process_name(bin_name: &str) {
+    if let Ok(name) = CString::new(bin_name) {
+        set_name(&name).ok();
+    }
+}
+
+#[cfg(any(feature = "preload", feature = "audit"))]
+fn extract_file(name: &str, data: &[u8]) -> Result<PathBuf> {
+    use std::{fs::{DirBuilder, rename}, os::unix::fs::DirBuilderExt, io::ErrorKind::{AlreadyExists, PermissionDenied},
+        hash::{Hash, Hasher, DefaultHasher}};
+
+    let uid = unsafe { libc::getuid() };
+    let cache_dir = match env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) if is_writable(&runtime_dir) => PathBuf::from(runtime_dir).join(SHARUN_NAME),
+        _ => env::temp_dir().join(format!("{SHARUN_NAME}-{uid}"))
+    };
+    if let Err(err) = DirBuilder::new().mode(0o700).create(&cache_dir) {
+        if err.kind() != AlreadyExists {
+            return Err(err)
+        }
+    }
+    let metadata = cache_dir.symlink_metadata()?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return Err(Error::new(PermissionDenied, format!("{} is not owned by the current user", cache_dir.display())))
+    }
+
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    let file_path = cache_dir.join(format!("{:016x}-{name}", hasher.finish()));
+    if file_path.metadata().is_ok_and(|metadata| metadata.len() == data.len() as u64) {
+        return Ok(file_path)
+    }
+    let tmp_path = cache_dir.join(format!(".{name}.{}", std::process::id()));
+    write(&tmp_path, data)?;
+    rename(&tmp_path, &file_path)?;
+    Ok(file_path)
+}
+
+#[cfg(feature = "preload")]
+fn get_preload_lib() -> Result<PathBuf> {
+    let preload_compressed = include_file_compress::include_file_compress_deflate!("libsharun_preload.so", 9);
+    let mut decoder = flate2::read::DeflateDecoder::new(&preload_compressed[..]);
+    let mut preload_lib = Vec::new();
+    decoder.read_to_end(&mut preload_lib)?;
+    extract_file("libsharun_preload.so", &preload_lib)
+}
+
+// dlsym is in libdl.so.2 before glibc 2.34, the preload library has no DT_NEEDED for it
+#[cfg(feature = "preload")]
+fn get_preload_deps(library_path: &str, sharun_dir: &str) -> Result<Vec<String>> {
+    let find_bundle_lib = |name: &str| library_path.split(":")
+        .filter(|dir| dir.starts_with(sharun_dir))
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.exists());
+    let Some(libc) = find_bundle_lib("libc.so.6") else { return Ok(vec![]) };
+    if contains_bytes(&read_elf_section(&libc, ".dynstr")?, b"GLIBC_2.34\0") {
+        return Ok(vec![])
+    }
+    find_bundle_lib("libdl.so.2")
+        .map(|libdl| vec![libdl.to_string_lossy().into()])
+        .ok_or_else(|| Error::new(NotFound, "the bundled glibc is older than 2.34 and there is no libdl.so.2 in the bundle"))
+}
+
+#[cfg(feature = "audit")]
+fn get_audit_lib() -> Result<PathBuf> {
+    let audit_compressed = include_file_compress::include_file_compress_deflate!("libsharun_audit.so", 9);
+    let mut decoder = flate2::read::DeflateDecoder::new(&audit_compressed[..]);
+    let mut audit_lib = Vec::new();
+    decoder.read_to_end(&mut audit_lib)?;
+    extract_file("libsharun_audit.so", &audit_lib)
+}
+
+#[cfg(feature = "sign")]
+fn check_sign_policy(sharun_dir: &str) {
+    if let Err(err) = sign::check_policy(sharun_dir) {
+        eprintln!("Refusing to run unsigned or tampered bundle: {sharun_dir}: {err}");
+        exit(1)
+    }
+}
+
+fn to_cstring<S: AsRef<OsStr>>(string: S) -> Result<CString> {
+    let string = string.as_ref();
+    CString::new(string.as_bytes()).map_err(|_| Error::new(InvalidInput,
+        format!("'{}' contains a NUL byte", string.to_string_lossy())))
+}
+
+fn to_cstrings<S: AsRef<OsStr>>(strings: &[S]) -> Result<Vec<CString>> {
+    strings.iter().map(to_cstring).collect()
+}
+
+fn get_envs() -> Result<Vec<CString>> {
+    env::vars_os().map(|(key, value)| {
+        let mut var = key;
+        var.push("=");
+        var.push(value);
+        to_cstring(var)
+    }).collect()
+}
+
+// the variables sharun sets to the bundle paths, and the usual path lists
+const BUNDLE_ENV_VARS: &[&str] = &[
+    "PATH", "LD_LIBRARY_PATH", "LD_PRELOAD", "LD_AUDIT", "APPDIR", "ARGV0",
+    "XDG_DATA_DIRS", "XDG_CONFIG_DIRS", "PYTHONPATH", "PYTHONHOME", "PERLLIB", "TCL_LIBRARY", "TK_LIBRARY",
+    "ALSA_CONFIG_PATH", "AMDGPU_ASIC_ID_TABLE_PATHS", "BABL_PATH", "DRIRC_CONFIGDIR", "FOLKS_BACKEND_PATH",
+    "FONTCONFIG_FILE", "GBM_BACKENDS_PATH", "GCONV_PATH", "GDK_PIXBUF_MODULEDIR", "GDK_PIXBUF_MODULE_FILE",
+    "GEGL_PATH", "GIO_LAUNCH_DESKTOP", "GIO_MODULE_DIR", "GI_TYPELIB_PATH", "GSETTINGS_SCHEMA_DIR",
+    "GST_PLUGIN_PATH", "GST_PLUGIN_SCANNER", "GST_PLUGIN_SYSTEM_PATH", "GST_PLUGIN_SYSTEM_PATH_1_0",
+    "GTK_DATA_PREFIX", "GTK_EXE_PREFIX", "GTK_IM_MODULE_FILE", "GTK_PATH", "IMLIB2_FILTER_PATH",
+    "IMLIB2_LOADER_PATH", "LIBDECOR_PLUGIN_DIR", "LIBGL_DRIVERS_PATH", "LIBHEIF_PLUGIN_PATH", "LIBTHAI_DICTDIR",
+    "LIBVA_DRIVERS_PATH", "MAGIC", "PIPEWIRE_MODULE_DIR", "QT_PLUGIN_PATH", "SPA_PLUGIN_DIR", "TERMINFO",
+    "TEXTDOMAINDIR", "VK_DRIVER_FILES", "XKB_CONFIG_ROOT", "XLOCALEDIR", "XTABLES_LIBDIR",
+    "__EGL_VENDOR_LIBRARY_DIRS", "__EGL_VENDOR_LIBRARY_FILENAMES"
+];
+
+// the names of the variables set by the .env file of a bundle
+fn get_dotenv_keys(dotenv_dir: &str) -> Vec<String> {
+    read_to_string(format!("{dotenv_dir}/.env")).unwrap_or_default().lines()
+        .map(|line| line.trim())
+        .map(|line| line.strip_prefix("export ").unwrap_or(line))
+        .filter_map(|line| line.split_once("=").map(|(key, _)| key.trim().to_string()))
+        .filter(|key| !key.is_empty() && !key.starts_with("#"))
+        .collect()
+}
+
+// only the variables set by sharun or by the .env of the other bundle, not PWD, OLDPWD, HISTFILE...
+fn remove_env_paths(dir: &str, keep_dir: &str) {
+    let is_in_dir = |path: &[u8], dir: &str| path.starts_with(dir.as_bytes()) &&
+        (path.len() == dir.len() || path[dir.len()] == b'/');
+    let is_removed = |path: &[u8]| is_in_dir(path, dir) && !is_in_dir(path, keep_dir);
+    let mut keys: Vec<String> = BUNDLE_ENV_VARS.iter().map(|key| key.to_string()).collect();
+    keys.append(&mut get_dotenv_keys(dir));
+    for key in keys {
+        let Some(val) = env::var_os(&key) else { continue };
+        let paths: Vec<&[u8]> = val.as_bytes().split(|&byte| byte == b':').collect();
+        if !paths.iter().any(|path| is_removed(path)) {
+            continue
+        }
+        let paths: Vec<&[u8]> = paths.into_iter().filter(|path| !is_removed(path)).collect();
+        if paths.iter().all(|path| path.is_empty()) {
+            env::remove_var(key)
+        } else {
+            env::set_var(key, OsStr::from_bytes(&paths.join(&b':')))
+        }
+    }
 }
 
 fn get_env_var<K: AsRef<OsStr>>(key: K) -> String {
@@ -278,28 +845,45 @@ fn get_env_var<K: AsRef<OsStr>>(key: K) -> String {
 }
 
 fn add_to_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(key: K, val: V) {
-    let (key, val) = (key.as_ref(), val.as_ref().to_str().unwrap_or_default());
-    let old_val = get_env_var(key);
+    let (key, val) = (key.as_ref(), val.as_ref());
+    let old_val = env::var_os(key).unwrap_or_default();
     if old_val.is_empty() {
         env::set_var(key, val)
-    } else if old_val != val &&
-      !old_val.starts_with(&format!("{val}:")) &&
-      !old_val.ends_with(&format!(":{val}")) &&
-      !old_val.contains(&format!(":{val}:")) {
-        env::set_var(key, format!("{val}:{old_val}"))
+    } else if !old_val.as_bytes().split(|&byte| byte == b':').any(|old_dir| old_dir == val.as_bytes()) {
+        let mut new_val = val.to_os_string();
+        new_val.push(":");
+        new_val.push(old_val);
+        env::set_var(key, new_val)
     }
 }
 
+fn print_env() -> bool {
+    if get_env_var("SHARUN_PRINTENV") == "1" {
+        env::remove_var("SHARUN_PRINTENV");
+        for (key, value) in env::vars_os() {
+            eprintln!("{}={}", key.to_string_lossy(), value.to_string_lossy())
+        }
+        return true
+    }
+    false
+}
+
 fn read_dotenv(dotenv_dir: &str) -> Vec<String> {
     let mut unset_envs = Vec::new();
     let dotenv_path = PathBuf::from(format!("{dotenv_dir}/.env"));
     if dotenv_path.exists() {
-        dotenv::from_path(&dotenv_path).ok();
-        let data = read_to_string(&dotenv_path).unwrap_or_else(|err|{
-            eprintln!("Failed to read .env file: {}: {err}", dotenv_path.display());
-            exit(1)
-        });
-        for string in data.trim().split("\n") {
+        // a broken .env should not make the bundle unusable
+        let data = match read(&dotenv_path) {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("Failed to read .env file: {}: {err}", dotenv_path.display());
+                return unset_envs
+            }
+        };
+        if let Err(err) = dotenv::from_path(&dotenv_path) {
+            eprintln!("Failed to load .env file: {}: {err}", dotenv_path.display())
+        }
+        for string in String::from_utf8_lossy(&data).trim().split("\n") {
             let string = string.trim();
             if string.starts_with("unset ") {
                 for var_name in string.split_whitespace().skip(1) {
@@ -311,6 +895,39 @@ fn read_dotenv(dotenv_dir: &str) -> Vec<String> {
     unset_envs
 }
 
+#[cfg(feature = "preload")]
+fn read_pathmap(sharun_dir: &str) -> Vec<String> {
+    let mut pathmap = Vec::new();
+    let pathmap_path = PathBuf::from(format!("{sharun_dir}/.pathmap"));
+    if pathmap_path.exists() {
+        let data = read_to_string(&pathmap_path).unwrap_or_else(|err|{
+            eprintln!("Failed to read .pathmap file: {}: {err}", pathmap_path.display());
+            exit(1)
+        });
+        for string in data.trim().split("\n") {
+            let string = string.trim();
+            if string.is_empty() || string.starts_with("#") {
+                continue
+            }
+            match string.split_once("=") {
+                Some((prefix, target)) if prefix.starts_with("/") && !target.trim().is_empty() => {
+                    let target = target.trim();
+                    if target.starts_with("/") {
+                        pathmap.push(format!("{}={target}", prefix.trim()))
+                    } else {
+                        pathmap.push(format!("{}={sharun_dir}/{target}", prefix.trim()))
+                    }
+                }
+                _ => {
+                    eprintln!("Invalid .pathmap rule: {string}");
+                    exit(1)
+                }
+            }
+        }
+    }
+    pathmap
+}
+
 #[cfg(feature = "setenv")]
 fn add_to_xdg_data_env(xdg_data_dirs: &str, env: &str, path: &str) {
     for xdg_data_dir in xdg_data_dirs.rsplit(":") {
@@ -321,7 +938,108 @@ fn add_to_xdg_data_env(xdg_data_dirs: &str, env: &str, path: &str) {
     }
 }
 
-fn gen_library_path(library_path: &str, lib_path_file: &String) {
+fn glob_paths(pattern: &str) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let Ok(pattern) = CString::new(pattern) else { return paths };
+    unsafe {
+        let mut glob: libc::glob_t = std::mem::zeroed();
+        if libc::glob(pattern.as_ptr(), 0, None, &mut glob) == 0 {
+            for i in 0..glob.gl_pathc {
+                let path = CStr::from_ptr(*glob.gl_pathv.add(i));
+                paths.push(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+            }
+        }
+        libc::globfree(&mut glob);
+    }
+    paths
+}
+
+fn read_ld_so_conf(conf_path: &Path, lib_dirs: &mut Vec<String>, conf_files: &mut Vec<String>, depth: u32) {
+    let Ok(data) = read_to_string(conf_path) else { return };
+    if let Ok(metadata) = conf_path.metadata() {
+        conf_files.push(format!("{}:{}:{}", conf_path.display(), metadata.mtime(), metadata.len()))
+    }
+    let conf_dir = conf_path.parent().unwrap_or(Path::new("/"));
+    for line in data.lines() {
+        let line = line.split("#").next().unwrap_or_default().trim();
+        if let Some(patterns) = line.strip_prefix("include") {
+            if depth < 8 {
+                for pattern in patterns.split_whitespace() {
+                    for include_path in glob_paths(&conf_dir.join(pattern).to_string_lossy()) {
+                        read_ld_so_conf(&include_path, lib_dirs, conf_files, depth + 1)
+                    }
+                }
+            }
+        } else if !line.starts_with("hwcap") {
+            // old ldconfig allowed 'dir=type', and the dirs may be separated by ':', ',' or spaces
+            for dir in line.split([':', ',', ' ', '\t']) {
+                let dir = dir.split("=").next().unwrap_or_default();
+                if dir.starts_with("/") && !lib_dirs.iter().any(|lib_dir| lib_dir == dir) {
+                    lib_dirs.push(dir.into())
+                }
+            }
+        }
+    }
+}
+
+fn is_lib_dir_of(lib_dir: &str, is_elf32: bool, machine: u16) -> bool {
+    let Ok(dir) = Path::new(lib_dir).read_dir() else { return false };
+    dir.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().contains(".so"))
+        .take(16)
+        .find_map(|entry| get_elf_info(&entry.path().to_string_lossy()).ok())
+        .is_some_and(|elf_info| elf_info.is_elf32 == is_elf32 && elf_info.machine == machine)
+}
+
+fn get_host_lib_dirs(is_elf32: bool, machine: u16) -> Vec<String> {
+    // ldconfig updates ld.so.cache after any change of ld.so.conf or the library dirs,
+    // but not everyone runs it, so every conf file counts too
+    let mut conf_dirs = Vec::new();
+    let mut conf_files = Vec::new();
+    read_ld_so_conf(Path::new("/etc/ld.so.conf"), &mut conf_dirs, &mut conf_files, 0);
+    let mtime = |path: &str| Path::new(path).metadata().map(|metadata| metadata.mtime()).unwrap_or_default();
+    let fingerprint = format!("{} {} {} {}",
+        read_to_string("/etc/machine-id").unwrap_or_default().trim(),
+        mtime("/etc/ld.so.conf.d"), mtime("/etc/ld.so.cache"), conf_files.join(" "));
+    let cache_home = match env::var("XDG_CACHE_HOME") {
+        Ok(cache_home) if !cache_home.is_empty() => cache_home,
+        _ => format!("{}/.cache", get_env_var("HOME"))
+    };
+    let cache_path = PathBuf::from(format!("{cache_home}/{SHARUN_NAME}/host-lib-dirs-{}-{machine}",
+        if is_elf32 { 32 } else { 64 }));
+
+    // no dirs is a valid result too, like for 32-bit binaries on a host without 32-bit libraries
+    let cache = read_to_string(&cache_path).unwrap_or_default();
+    let mut lib_dirs: Vec<String> = match cache.split_once("\n") {
+        Some((cache_fingerprint, cache_dirs)) if cache_fingerprint == fingerprint => {
+            cache_dirs.lines().map(String::from).collect()
+        }
+        _ => {
+            let lib_dirs: Vec<String> = conf_dirs.into_iter().filter(|dir| is_lib_dir_of(dir, is_elf32, machine)).collect();
+            if let Some(cache_dir) = cache_path.parent() {
+                if std::fs::create_dir_all(cache_dir).is_ok() {
+                    write(&cache_path, format!("{fingerprint}\n{}", lib_dirs.join("\n"))).ok();
+                }
+            }
+            lib_dirs
+        }
+    };
+
+    // dirs that are not in ld.so.conf, like nix-ld and Guix profiles
+    let mut env_dirs: Vec<String> = get_env_var("NIX_LD_LIBRARY_PATH").split(":")
+        .filter(|dir| dir.starts_with("/")).map(String::from).collect();
+    for profile in [format!("{}/.guix-profile", get_env_var("HOME")), "/run/current-system/profile".into()] {
+        env_dirs.push(format!("{profile}/lib"))
+    }
+    for dir in env_dirs {
+        if !lib_dirs.contains(&dir) && is_lib_dir_of(&dir, is_elf32, machine) {
+            lib_dirs.push(dir)
+        }
+    }
+    lib_dirs
+}
+
+fn get_library_path_data(library_path: &str) -> String {
     let mut new_paths: Vec<String> = Vec::new();
     let skip_dirs = ["lib-dynload".to_string()];
     WalkDir::new(library_path)
@@ -341,11 +1059,13 @@ fn gen_library_path(library_path: &str, lib_path_file: &String) {
                 }
             }
         });
-    if let Err(err) = write(lib_path_file,
-        format!("+:{}", &new_paths.join(":"))
-            .replace(":", "\n")
-            .replace(library_path, "+")
-    ) {
+    format!("+:{}", &new_paths.join(":"))
+        .replace(":", "\n")
+        .replace(library_path, "+")
+}
+
+fn gen_library_path(library_path: &str, lib_path_file: &String) {
+    if let Err(err) = write(lib_path_file, get_library_path_data(library_path)) {
         eprintln!("Failed to write lib.path: {lib_path_file}: {err}");
         exit(1)
     } else {
@@ -383,7 +1103,25 @@ fn print_usage() {
     #[cfg(feature = "lib4bin")]
     println!("     l,  lib4bin [ARGS]         Launch the built-in lib4bin");
     println!("    -g,  --gen-lib-path         Generate a lib.path file
-    -v,  --version              Print version
+         install [BIN]...       Install bin symlinks, desktop files and icons for the user
+         uninstall              Remove everything installed by 'install'
+         collect [-d DIR] [-p] [-l LIST] FILE...
+                                Copy the binaries and their libraries to the sharun dir (default: .)
+                                without lib4bin, -p creates hard links instead of symlinks in 'bin',
+                                -l adds the files from the list of 'trace'");
+    #[cfg(feature = "trace")]
+    println!("         trace [-t SECS] [-o FILE] COMMAND [ARGS]...
+                                Run the command with ptrace and list the binaries, libraries and data
+                                files it loads, for N seconds or until it exits (default: stdout)");
+    #[cfg(feature = "verify")]
+    println!("         verify [--create]      Check the bundle files against '.manifest' or create it");
+    #[cfg(feature = "sign")]
+    println!("         verify --pubkey FILE   Also check the signature of '.manifest' with the public key
+         sign --key FILE        Create '.manifest' and sign it with the Ed25519 private key");
+    #[cfg(feature = "pack")]
+    println!("         pack DIR -o FILE       Pack the sharun dir into a single executable file
+         FILE --extract [DIR]   Extract the bundle from the packed file (default: FILE_extracted)");
+    println!("    -v,  --version              Print version
     -h,  --help                 Print help
 
 [ Environments ]:
@@ -393,21 +1131,57 @@ fn print_usage() {
     SHARUN_ALLOW_QT_PLUGIN_PATH=1  Enables breaking QT_PLUGIN_PATH env variable
     SHARUN_NO_NVIDIA_EGL_PRIME=1   Disables NVIDIA EGL prime logic
     SHARUN_PRINTENV=1              Print environment variables to stderr
-    SHARUN_LDNAME=ld.so            Specifies the name of the interpreter
+    SHARUN_LDNAME=ld.so            Specifies the name of the interpreter if PT_INTERP is not in the bundle
+    SHARUN_EMULATOR=box64          Specifies the emulator for foreign architecture binaries (default: qemu-$ARCH)
+    SHARUN_PRELOAD_EXE=1           Makes /proc/self/exe point to the binary in 'shared/bin' (needs preload feature)
+    SHARUN_EXEC=kernel             Specifies how to exec the binary: auto, userland, kernel or direct (default: auto)
+    SHARUN_STRICT=1                Use only the bundle libraries, the launch is aborted if some would come from the host
+    SHARUN_STRICT=report           Only report the libraries that would come from the host and launch as usual
+    SHARUN_AUDIT=1                 Redirects dlopen of absolute host paths into the bundle (needs audit feature, glibc)
+    SHARUN_AUDIT_DEBUG=1           Print every library search of the audit library to stderr
+    SHARUN_VERIFY=1                Check sizes and mtimes of the bundle files against '.manifest' before launch
+    SHARUN_NO_HOOKS=1              Disables the pre-launch hooks from the '.hooks' dir
+    SHARUN_HOOKS_TIMEOUT=10        Specifies the timeout of each hook in seconds (default: 10)
     SHARUN_EXTRA_LIBRARY_PATH      Extra library directories with highest priority
     SHARUN_FALLBACK_LIBRARY_PATH   Fallback library directories with lowest priority
     SHARUN_DIR                     Sharun directory");
 }
 
 fn main() {
-    let sharun = env::current_exe().unwrap_or_else(|err|{
+    #[cfg_attr(not(feature = "pack"), allow(unused_mut))]
+    let mut sharun = env::current_exe().unwrap_or_else(|err|{
         eprintln!("Failed to get sharun path: {err}");
         exit(1)
     });
+    let exe_name = sharun.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    let mut exec_args: Vec<OsString> = env::args_os().collect();
+
+    // a packed sharun runs as the sharun of the bundle extracted from its payload
+    #[cfg(feature = "pack")]
+    let is_packed = if let Some(payload) = pack::Payload::find(&sharun) {
+        if exec_args.get(1).is_some_and(|arg| arg == "--extract") {
+            let dest = exec_args.get(2).map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{exe_name}_extracted")));
+            if let Err(err) = pack::extract(&sharun, &payload, &dest) {
+                eprintln!("Failed to extract: {}: {err}", dest.display());
+                exit(1)
+            }
+            println!("Extracted to: {}", dest.display());
+            return
+        }
+        let unpacked_dir = pack::unpack_cached(&sharun, &payload).unwrap_or_else(|err|{
+            eprintln!("Failed to unpack: {}: {err}", sharun.display());
+            exit(1)
+        });
+        sharun = unpacked_dir.join(SHARUN_NAME);
+        true
+    } else {
+        false
+    };
 
-    let mut exec_args: Vec<String> = env::args().collect();
-
-    let mut sharun_dir = realpath(&get_env_var("SHARUN_DIR"));
+    let inherited_sharun_dir = realpath(&get_env_var("SHARUN_DIR"));
+    let mut sharun_dir = inherited_sharun_dir.clone();
     if sharun_dir.is_empty() ||
         !(is_dir(&sharun_dir) && {
             let sharun_dir_path = Path::new(&sharun_dir);
@@ -416,10 +1190,15 @@ fn main() {
             is_same_rootdir(sharun_dir_path, &sharun, &sharun_path)
         })
     {
-        sharun_dir = sharun.parent().unwrap_or_else(||{
+        let sharun_parent = sharun.parent().unwrap_or_else(||{
             eprintln!("Failed to get sharun parrent dir!");
             exit(1)
-        }).to_str().unwrap_or_default().to_string();
+        });
+        // it is used in all the paths and exported, an empty one would point at the cwd
+        sharun_dir = sharun_parent.to_str().unwrap_or_else(||{
+            eprintln!("Unsupported sharun dir: {}: the path is not valid UTF-8", sharun_parent.display());
+            exit(1)
+        }).to_string();
         let lower_dir = &format!("{sharun_dir}/../");
         if basename(&sharun_dir) == "bin" &&
             is_dir(&format!("{lower_dir}shared")) {
@@ -428,6 +1207,12 @@ fn main() {
         env::set_var("SHARUN_DIR", &sharun_dir)
     }
 
+    // started by a binary from another bundle, its paths must not leak into this one
+    if !inherited_sharun_dir.is_empty() && inherited_sharun_dir != sharun_dir {
+        remove_env_paths(&inherited_sharun_dir, &sharun_dir)
+    }
+    drop(inherited_sharun_dir);
+
     let bin_dir = &format!("{sharun_dir}/bin");
     let shared_dir = &format!("{sharun_dir}/shared");
     let shared_bin = &format!("{shared_dir}/bin");
@@ -435,8 +1220,8 @@ fn main() {
     let shared_lib32 = format!("{shared_dir}/lib32");
 
     let arg0 = PathBuf::from(exec_args.remove(0));
-    let arg0_name = arg0.file_name().unwrap_or_default().to_str().unwrap_or_default();
-    let arg0_dir = PathBuf::from(dirname(arg0.to_str().unwrap_or_default())).canonicalize()
+    let arg0_name = arg0.file_name().unwrap_or_default();
+    let arg0_dir = arg0.parent().unwrap_or(Path::new("")).canonicalize()
         .unwrap_or_else(|_|{
             if let Some(which_arg0) = which(arg0_name) {
                 which_arg0.parent().unwrap_or_else(||{
@@ -454,18 +1239,31 @@ fn main() {
     let arg0_full_path_name = arg0_full_path.file_name().unwrap_or_default().to_string_lossy().to_string();
     let mut bin_name = if arg0_path.is_symlink() &&
         arg0_full_path == Path::new(&sharun_dir).join(SHARUN_NAME) {
-        arg0_name.into()
+        arg0_name.to_string_lossy().into()
     } else if arg0_path.is_symlink() && Path::new(&shared_bin).join(&arg0_full_path_name).exists() {
         arg0_full_path_name
     } else {
-        sharun.file_name().unwrap_or_default().to_string_lossy().to_string()
+        exe_name
     };
     drop(arg0_dir);
     drop(arg0_full_path);
 
+    // a packed file named not after a bundle binary starts the app of the bundle
+    #[cfg(feature = "pack")]
+    if is_packed && bin_name != SHARUN_NAME &&
+        !Path::new(&shared_bin).join(&bin_name).exists() && !Path::new(&bin_dir).join(&bin_name).exists() {
+        bin_name = "AppRun".into()
+    }
+
+    // before anything from the bundle is read or executed
+    #[cfg(feature = "sign")]
+    if bin_name != SHARUN_NAME {
+        check_sign_policy(&sharun_dir)
+    }
+
     if bin_name == SHARUN_NAME {
         if !exec_args.is_empty() {
-            match exec_args[0].as_str() {
+            match exec_args[0].to_str().unwrap_or_default() {
                 "-v" | "--version" => {
                     println!("v{}", env!("CARGO_PKG_VERSION"));
                     return
@@ -483,6 +1281,194 @@ fn main() {
                     }
                     return
                 }
+                "install" => {
+                    let bins: Vec<String> = exec_args[1..].iter().map(|bin| bin.to_str()
+                        .unwrap_or_else(||{
+                            eprintln!("Invalid executable name: {}: it is not valid UTF-8", bin.to_string_lossy());
+                            exit(1)
+                        }).into()).collect();
+                    if let Err(err) = install::install(&sharun_dir, &bins) {
+                        eprintln!("Failed to install: {err}");
+                        exit(1)
+                    }
+                    return
+                }
+                "uninstall" => {
+                    if let Err(err) = install::uninstall(&sharun_dir) {
+                        eprintln!("Failed to uninstall: {err}");
+                        exit(1)
+                    }
+                    return
+                }
+                "collect" => {
+                    let mut dst_dir = PathBuf::from(".");
+                    let mut hard_links = false;
+                    let mut files = Vec::new();
+                    let mut data_files = Vec::new();
+                    let mut args = exec_args[1..].iter();
+                    while let Some(arg) = args.next() {
+                        match arg.to_str().unwrap_or_default() {
+                            "-d" | "--dst-dir" => dst_dir = args.next().map(PathBuf::from).unwrap_or_else(||{
+                                eprintln!("Specify the destination dir: {SHARUN_NAME} collect -d DIR FILE...");
+                                exit(1)
+                            }),
+                            "-p" | "--hard-links" => hard_links = true,
+                            "-l" | "--list" => {
+                                let list = args.next().map(PathBuf::from).unwrap_or_else(||{
+                                    eprintln!("Specify the list file: {SHARUN_NAME} collect -d DIR -l LIST");
+                                    exit(1)
+                                });
+                                let list = collect::read_list(&list).unwrap_or_else(|err|{
+                                    eprintln!("Failed to read list: {err}");
+                                    exit(1)
+                                });
+                                files.extend(list.files);
+                                data_files.extend(list.data)
+                            }
+                            _ => files.push(PathBuf::from(arg))
+                        }
+                    }
+                    if files.is_empty() && data_files.is_empty() {
+                        eprintln!("Specify the executable or shared object: {SHARUN_NAME} collect -d DIR FILE...");
+                        exit(1)
+                    }
+                    let collected = collect::collect(&sharun, &files, &data_files, &dst_dir, hard_links).unwrap_or_else(|err|{
+                        eprintln!("Failed to collect: {err}");
+                        exit(1)
+                    });
+                    for lib in &collected.missing {
+                        eprintln!("Library not found: {lib}")
+                    }
+                    for lib_dir in ["lib", "lib32"] {
+                        let library_path = dst_dir.join("shared").join(lib_dir).to_string_lossy().to_string();
+                        if Path::new(&library_path).exists() {
+                            gen_library_path(&library_path, &format!("{library_path}/lib.path"))
+                        }
+                    }
+                    println!("Collected {} binaries, {} libraries and {} data files to: {}",
+                        collected.bins, collected.libs, collected.data, dst_dir.display());
+                    if !collected.missing.is_empty() {
+                        exit(1)
+                    }
+                    return
+                }
+                #[cfg(feature = "trace")]
+                "trace" => {
+                    let mut timeout = None;
+                    let mut output = None;
+                    let mut args = exec_args[1..].iter().peekable();
+                    while let Some(arg) = args.next_if(|arg| arg.to_string_lossy().starts_with('-')) {
+                        let value = args.next().unwrap_or_else(||{
+                            eprintln!("Specify the value of {}", arg.to_string_lossy());
+                            exit(1)
+                        });
+                        match arg.to_str().unwrap_or_default() {
+                            "-t" | "--timeout" => timeout = Some(value.to_str().unwrap_or_default().parse()
+                                .map(std::time::Duration::from_secs).unwrap_or_else(|_|{
+                                    eprintln!("Invalid timeout: '{}', expected seconds", value.to_string_lossy());
+                                    exit(1)
+                                })),
+                            "-o" | "--output" => output = Some(PathBuf::from(value)),
+                            _ => {
+                                eprintln!("Invalid trace option: {}", arg.to_string_lossy());
+                                exit(1)
+                            }
+                        }
+                    }
+                    let command: Vec<OsString> = args.cloned().collect();
+                    if command.is_empty() {
+                        eprintln!("Specify the command: {SHARUN_NAME} trace [-t SECS] [-o FILE] COMMAND [ARGS]...");
+                        exit(1)
+                    }
+                    let list = trace::trace(&command, timeout).unwrap_or_else(|err|{
+                        eprintln!("Failed to trace: {}: {err}", command[0].to_string_lossy());
+                        exit(1)
+                    });
+                    let list: String = list.iter().map(|line| format!("{line}\n")).collect();
+                    match output {
+                        Some(output) => if let Err(err) = write(&output, list) {
+                            eprintln!("Failed to write trace list: {}: {err}", output.display());
+                            exit(1)
+                        }
+                        None => print!("{list}")
+                    }
+                    return
+                }
+                #[cfg(feature = "verify")]
+                "verify" => {
+                    let args: Vec<&str> = exec_args[1..].iter().map(|arg| arg.to_str().unwrap_or_default()).collect();
+                    match args[..] {
+                        [] => {}
+                        ["--create"] => {
+                            match manifest::create_manifest(&sharun_dir) {
+                                Ok(count) => println!("Write {}: {count} files", manifest::MANIFEST_NAME),
+                                Err(err) => {
+                                    eprintln!("Failed to create manifest: {err}");
+                                    exit(1)
+                                }
+                            }
+                            return
+                        }
+                        #[cfg(feature = "sign")]
+                        ["--pubkey", pubkey] => {
+                            if let Err(err) = sign::verify_with_pubkey(&sharun_dir, Path::new(pubkey)) {
+                                eprintln!("Failed to verify signature: {err}");
+                                exit(1)
+                            }
+                            println!("Signature: OK")
+                        }
+                        _ => {
+                            eprintln!("Invalid verify arguments: {}", args.join(" "));
+                            exit(1)
+                        }
+                    }
+                    let (problems, extra) = manifest::verify(&sharun_dir).unwrap_or_else(|err|{
+                        eprintln!("Failed to verify bundle: {err}");
+                        exit(1)
+                    });
+                    for path in &extra {
+                        println!("extra: {}", path.display())
+                    }
+                    for line in &problems {
+                        println!("{line}")
+                    }
+                    if !problems.is_empty() {
+                        exit(1)
+                    }
+                    return
+                }
+                #[cfg(feature = "sign")]
+                "sign" => {
+                    let Some(key) = exec_args.get(2).filter(|_| exec_args[1] == "--key") else {
+                        eprintln!("Specify the private key: {SHARUN_NAME} sign --key ed25519.key");
+                        exit(1)
+                    };
+                    match sign::sign(&sharun_dir, Path::new(key)) {
+                        Ok(pubkey) => println!("Write {}, public key: {pubkey}", manifest::SIGNATURE_NAME),
+                        Err(err) => {
+                            eprintln!("Failed to sign bundle: {err}");
+                            exit(1)
+                        }
+                    }
+                    return
+                }
+                #[cfg(feature = "pack")]
+                "pack" => {
+                    let args: Vec<&OsString> = exec_args[1..].iter().collect();
+                    let (dir, output) = match args[..] {
+                        [dir, opt, output] | [opt, output, dir] if opt == "-o" => (dir, output),
+                        _ => {
+                            eprintln!("Specify the dir and the output file: {SHARUN_NAME} pack DIR -o FILE");
+                            exit(1)
+                        }
+                    };
+                    if let Err(err) = pack::pack(Path::new(dir), &sharun, Path::new(output)) {
+                        eprintln!("Failed to pack: {}: {err}", Path::new(dir).display());
+                        exit(1)
+                    }
+                    println!("Packed to: {}", Path::new(output).display());
+                    return
+                }
                 #[cfg(feature = "lib4bin")]
                 "l" | "lib4bin" => {
                     let lib4bin_compressed = include_file_compress::include_file_compress_deflate!("lib4bin", 9);
@@ -516,7 +1502,12 @@ fn main() {
                     }
                 }
                 _ => {
-                    bin_name = exec_args.remove(0);
+                    #[cfg(feature = "sign")]
+                    check_sign_policy(&sharun_dir);
+                    bin_name = exec_args.remove(0).into_string().unwrap_or_else(|bin_name|{
+                        eprintln!("Invalid executable name: {}: it is not valid UTF-8", bin_name.to_string_lossy());
+                        exit(1)
+                    });
                     let bin_path = PathBuf::from(bin_dir).join(&bin_name);
                     if let Ok(bin_full_path) = bin_path.canonicalize() {
                         let bin_full_path_name = bin_full_path.file_name().unwrap_or_default().to_string_lossy().to_string();
@@ -529,6 +1520,13 @@ fn main() {
                             bin_full_path != sharun)
                         {
                             add_to_env("PATH", bin_dir);
+                            // otherwise sharun runs them itself after the exec
+                            if !is_hardlink(&sharun, &bin_full_path) {
+                                if let Err(err) = hooks::run_hooks(&sharun_dir, &bin_name) {
+                                    eprintln!("Failed to run hooks: {err}");
+                                    exit(1)
+                                }
+                            }
                             match is_script(&bin_path) {
                                 Ok(true) => {
                                     if let Err(err) = exec_script(&bin_path, &exec_args) {
@@ -565,41 +1563,27 @@ fn main() {
         }
     } else if bin_name == "AppRun" {
         let appname_file = &format!("{sharun_dir}/.app");
-        let mut appname: String = "".into();
-        if !Path::new(appname_file).exists() {
-            if let Ok(dir) = Path::new(&sharun_dir).read_dir() {
-                for entry in dir.flatten() {
-                    let path = entry.path();
-                    if is_file(&path) {
-                        let name = entry.file_name();
-                        let name = name.to_str().unwrap_or_default();
-                        if name.ends_with(".desktop") {
-                            let data = read_to_string(path).unwrap_or_else(|err|{
-                                eprintln!("Failed to read desktop file: {name}: {err}");
-                                exit(1)
-                            });
-                            appname = data.split("\n").filter_map(|string| {
-                                if string.starts_with("Exec=") {
-                                    Some(string.replace("Exec=", "").split_whitespace().next().unwrap_or("").into())
-                                } else {None}
-                            }).next().unwrap_or_else(||"".into())
-                        }
-                    }
-                }
-            }
-        }
-
-        if appname.is_empty() {
-            appname = read_to_string(appname_file).unwrap_or_else(|err|{
+        let (appname, app_args, is_terminal_app) = if Path::new(appname_file).exists() {
+            let appname = read_to_string(appname_file).unwrap_or_else(|err|{
                 eprintln!("Failed to read .app file: {appname_file}: {err}");
                 exit(1)
-            })
-        }
-
-        if let Some(name) = appname.trim().split("\n").next() {
-            appname = basename(name)
-            .replace("'", "").replace("\"", "")
+            });
+            let appname = basename(appname.trim().split("\n").next().unwrap_or_default())
+                .replace("'", "").replace("\"", "");
+            (appname, exec_args, false)
         } else {
+            let entry = desktop::find_desktop_entry(&sharun_dir).unwrap_or_else(|err|{
+                eprintln!("Failed to find desktop file: {err}");
+                exit(1)
+            });
+            let mut app_args = entry.expand_exec(&exec_args).unwrap_or_else(|err|{
+                eprintln!("Failed to parse desktop file: {}: {err}", entry.path.display());
+                exit(1)
+            });
+            let appname = basename(&app_args.remove(0).to_string_lossy());
+            (appname, app_args, entry.is_true("Terminal"))
+        };
+        if appname.is_empty() {
             eprintln!("Failed to get app name: {appname_file}");
             exit(1)
         }
@@ -612,24 +1596,60 @@ fn main() {
         if get_env_var("APPDIR").is_empty() {
             env::set_var("APPDIR", &sharun_dir)
         }
+        // otherwise sharun runs them itself after the exec
+        if !is_hardlink(&sharun, Path::new(app)) {
+            if let Err(err) = hooks::run_hooks(&sharun_dir, &appname) {
+                eprintln!("Failed to run hooks: {err}");
+                exit(1)
+            }
+        }
+
+        let mut command = vec![OsString::from(app)];
+        // a terminal is opened only if none of the standard streams is already attached to one
+        if is_terminal_app && [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+            .iter().all(|&fd| unsafe { libc::isatty(fd) } == 0) {
+            match desktop::get_terminal() {
+                Some(terminal) => { command.splice(0..0, terminal); }
+                None => eprintln!("Failed to find a terminal for App: {app}")
+            }
+        }
 
-        let err = Command::new(app)
-            .args(exec_args)
+        let err = Command::new(&command[0])
+            .args(&command[1..])
+            .args(app_args)
             .exec();
         eprintln!("Failed to run App: {app}: {err}");
         exit(1)
     }
     let bin = format!("{shared_bin}/{bin_name}");
 
-    cfg_if! {
-        if #[cfg(feature = "elf32")] {
-            let is_elf32_bin = is_elf32(&bin).unwrap_or_else(|err|{
-                eprintln!("Failed to check ELF class: {bin}: {err}");
-                exit(1)
-            });
-        } else {
-            let is_elf32_bin = false;
-        }
+    let bin_info = get_elf_info(&bin).unwrap_or_else(|err|{
+        eprintln!("Cannot run {bin}: {err}");
+        exit(1)
+    });
+
+    // static and static-pie binaries have no PT_INTERP and need neither the loader nor the libraries
+    let is_static_bin = bin_info.interp.is_none();
+
+    let emulator = if is_native_elf(&bin_info) {
+        None
+    } else {
+        let qemu_arch = get_elf_arch(&bin_info).unwrap_or_else(||{
+            eprintln!("Cannot run {bin}: unsupported {}", describe_elf(&bin_info));
+            exit(1)
+        });
+        Some(get_emulator(shared_bin, qemu_arch).unwrap_or_else(||{
+            eprintln!("Cannot run {qemu_arch} binary on {}: {bin}", env::consts::ARCH);
+            eprintln!("Install qemu-user, add qemu-{qemu_arch} to '{shared_bin}' or set SHARUN_EMULATOR");
+            exit(1)
+        }))
+    };
+    let is_foreign_bin = emulator.is_some();
+
+    let is_elf32_bin = bin_info.is_elf32;
+    if is_elf32_bin && cfg!(not(feature = "elf32")) && cfg!(target_pointer_width = "64") {
+        eprintln!("Cannot run {bin}: sharun was built without 32-bit ELF support");
+        exit(1)
     }
 
     cfg_if! {
@@ -643,11 +1663,16 @@ fn main() {
         }
     }
 
-    let mut library_path = if is_elf32_bin {
+    // on 32-bit hosts ELF32 is the native class and lib4bin puts its libraries into 'shared/lib'
+    let is_lib32_bin = is_elf32_bin &&
+        ((cfg!(target_pointer_width = "64") && !is_foreign_bin) || Path::new(&shared_lib32).exists());
+
+    let mut library_path = if is_lib32_bin {
         shared_lib32
     } else {
         shared_lib
     };
+    let bundle_lib_dir = library_path.clone();
 
     let unset_envs = read_dotenv(&sharun_dir);
 
@@ -661,10 +1686,32 @@ fn main() {
     }
     env::remove_var("SHARUN_ALLOW_QT_PLUGIN_PATH");
 
-    let interpreter = get_interpreter(&library_path).unwrap_or_else(|_|{
-        eprintln!("Interpreter not found!");
-        exit(1)
-    });
+    let strict_mode = get_env_var("SHARUN_STRICT");
+    let (is_strict, is_strict_report) = (strict_mode == "1", strict_mode == "report");
+    env::remove_var("SHARUN_STRICT");
+
+    let mut exec_strategy = if is_static_bin {
+        ExecStrategy::Direct
+    } else {
+        get_exec_strategy()
+    };
+    env::remove_var("SHARUN_EXEC");
+
+    // a precise error instead of a loader crash on a partly copied or extracted bundle
+    if get_env_var("SHARUN_VERIFY") == "1" {
+        let problems = manifest::check_quick(&sharun_dir).unwrap_or_else(|err|{
+            eprintln!("Failed to check bundle: {err}");
+            exit(1)
+        });
+        if !problems.is_empty() {
+            eprintln!("Bundle is damaged: {sharun_dir}:");
+            for problem in problems {
+                eprintln!("    {problem}")
+            }
+            exit(1)
+        }
+    }
+    env::remove_var("SHARUN_VERIFY");
 
     let working_dir = &get_env_var("SHARUN_WORKING_DIR");
     if !working_dir.is_empty() {
@@ -735,10 +1782,7 @@ fn main() {
                             add_to_env("LIBVA_DRIVERS_PATH", "/run/opengl-driver/lib/dri");
                             add_to_env("LIBVA_DRIVERS_PATH", "/usr/lib/dri");
                             add_to_env("LIBVA_DRIVERS_PATH", "/usr/lib64/dri");
-                            #[cfg(target_arch = "x86_64")]
-                            add_to_env("LIBVA_DRIVERS_PATH", "/usr/lib/x86_64-linux-gnu/dri");
-                            #[cfg(target_arch = "aarch64")]
-                            add_to_env("LIBVA_DRIVERS_PATH", "/usr/lib/aarch64-linux-gnu/dri");
+                            add_to_env("LIBVA_DRIVERS_PATH", format!("/usr/lib/{MULTIARCH}/dri"));
                     }
                     add_to_env("LIBVA_DRIVERS_PATH", dir_path)
                 }
@@ -746,10 +1790,7 @@ fn main() {
                     add_to_env("GBM_BACKENDS_PATH", "/run/opengl-driver/lib/gbm");
                     add_to_env("GBM_BACKENDS_PATH", "/usr/lib/gbm");
                     add_to_env("GBM_BACKENDS_PATH", "/usr/lib64/gbm");
-                    #[cfg(target_arch = "x86_64")]
-                    add_to_env("GBM_BACKENDS_PATH", "/usr/lib/x86_64-linux-gnu/gbm");
-                    #[cfg(target_arch = "aarch64")]
-                    add_to_env("GBM_BACKENDS_PATH", "/usr/lib/aarch64-linux-gnu/gbm");
+                    add_to_env("GBM_BACKENDS_PATH", format!("/usr/lib/{MULTIARCH}/gbm"));
                     add_to_env("GBM_BACKENDS_PATH", dir_path)
                 }
                 if dir == "libheif" {
@@ -1034,48 +2075,116 @@ fn main() {
 
     drop(lib_path_data);
 
-    let ld_library_path_env = &get_env_var("LD_LIBRARY_PATH");
-    if !ld_library_path_env.is_empty() {
-        library_path += &format!(":{ld_library_path_env}")
-    }
-
     let extra_library_path = get_env_var("SHARUN_EXTRA_LIBRARY_PATH");
     if !extra_library_path.is_empty() {
         library_path = format!("{}:{}", extra_library_path, library_path);
         env::remove_var("SHARUN_EXTRA_LIBRARY_PATH");
     }
+    // the library path of the strict mode, to report what would come from the host
+    let strict_library_path = if is_strict_report { library_path.clone() } else { String::new() };
 
-    library_path += ":/usr/lib:/lib";
-    if is_elf32_bin {
-        library_path += ":/usr/lib32:/lib32";
-        #[cfg(target_arch = "x86_64")]
-        { library_path += ":/usr/lib/i386-linux-gnu" }
-    } else {
-        library_path += ":/usr/lib64:/lib64";
-        #[cfg(target_arch = "x86_64")]
-        { library_path += ":/usr/lib/x86_64-linux-gnu" }
-        #[cfg(target_arch = "aarch64")]
-        { library_path += ":/usr/lib/aarch64-linux-gnu" }
+    let ld_library_path_env = &get_env_var("LD_LIBRARY_PATH");
+    if !ld_library_path_env.is_empty() && !is_strict {
+        library_path += &format!(":{ld_library_path_env}")
+    }
+
+    // host libraries are of no use to a foreign architecture binary
+    if !is_foreign_bin && !is_strict {
+        for lib_dir in get_host_lib_dirs(is_elf32_bin, bin_info.machine) {
+            library_path += &format!(":{lib_dir}")
+        }
+        // the usual dirs are the last resort if ld.so.conf lacks them
+        library_path += ":/usr/lib:/lib";
+        if cfg!(target_pointer_width = "32") {
+            library_path += &format!(":/usr/lib/{MULTIARCH}:/lib/{MULTIARCH}")
+        } else if is_elf32_bin {
+            library_path += ":/usr/lib32:/lib32";
+            #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+            { library_path += &format!(":/usr/lib/{MULTIARCH32}:/lib/{MULTIARCH32}") }
+        } else {
+            library_path += ":/usr/lib64:/lib64";
+            library_path += &format!(":/usr/lib/{MULTIARCH}:/lib/{MULTIARCH}");
+            #[cfg(target_arch = "riscv64")]
+            { library_path += ":/usr/lib64/lp64d:/lib64/lp64d" }
+        }
+        library_path += ":/run/opengl-driver/lib:/run/current-system/sw/lib";
     }
-    library_path += ":/run/opengl-driver/lib:/run/current-system/sw/lib";
 
     let fallback_library_path = get_env_var("SHARUN_FALLBACK_LIBRARY_PATH");
-    if !fallback_library_path.is_empty() {
+    // strict mode keeps everything outside of the bundle out of the library path
+    if !fallback_library_path.is_empty() && !is_strict {
         library_path = format!("{}:{}", library_path, fallback_library_path);
-        env::remove_var("SHARUN_FALLBACK_LIBRARY_PATH");
     }
+    env::remove_var("SHARUN_FALLBACK_LIBRARY_PATH");
 
     for var_name in unset_envs {
         env::remove_var(var_name)
     }
 
-    if get_env_var("SHARUN_PRINTENV") == "1" {
-        env::remove_var("SHARUN_PRINTENV");
-        for (key, value) in env::vars_os() {
-            eprintln!("{}={}", key.to_string_lossy(), value.to_string_lossy())
+    if let Err(err) = hooks::run_hooks(&sharun_dir, &bin_name) {
+        eprintln!("Failed to run hooks: {err}");
+        exit(1)
+    }
+
+    // the loader still searches its default directories, so fail instead of loading host libraries
+    if is_strict || is_strict_report {
+        let missing_libs = get_missing_libs(&bin, if is_strict { &library_path } else { &strict_library_path });
+        if !missing_libs.is_empty() {
+            eprintln!("Strict mode: {bin} needs libraries that are not in the bundle:");
+            for lib in missing_libs {
+                eprintln!("    {lib}")
+            }
+            if is_strict {
+                exit(1)
+            }
         }
     }
 
+    if exec_strategy == ExecStrategy::Direct {
+        drop(elf_bytes);
+        if !is_static_bin {
+            env::set_var("LD_LIBRARY_PATH", &library_path)
+        }
+        print_env();
+        let err = if let Some(emulator) = emulator {
+            let interpreter_args: Vec<OsString> = [bin.clone().into()].into_iter().chain(exec_args.clone()).collect();
+            exec_emulator(&emulator, shared_dir, &library_path, &interpreter_args, &bin, &exec_args)
+        } else {
+            Command::new(&bin)
+                .arg0(&arg0_path)
+                .args(&exec_args)
+                .exec()
+        };
+        eprintln!("Failed to exec: {bin}: {err}");
+        exit(1)
+    }
+
+    let interpreter = get_interpreter(&bundle_lib_dir, bin_info.interp.as_deref()).unwrap_or_else(|_|{
+        eprintln!("Interpreter not found in '{bundle_lib_dir}' for {bin}: expected {}",
+            bin_info.interp.as_deref().map(basename).unwrap_or_else(|| "a dynamic loader".into()));
+        exit(1)
+    });
+    let interpreter_info = get_elf_info(&interpreter.to_string_lossy()).unwrap_or_else(|err|{
+        eprintln!("Invalid interpreter: {}: {err}", interpreter.display());
+        exit(1)
+    });
+    if interpreter_info.machine != bin_info.machine ||
+        interpreter_info.is_elf32 != bin_info.is_elf32 ||
+        interpreter_info.is_le != bin_info.is_le {
+        eprintln!("Interpreter mismatch: {bin} is {}, but {} is {}",
+            describe_elf(&bin_info), interpreter.display(), describe_elf(&interpreter_info));
+        exit(1)
+    }
+    let loader = get_loader(&interpreter).unwrap_or_else(|err|{
+        eprintln!("Unsupported interpreter: {}: {err}", interpreter.display());
+        exit(1)
+    });
+    // glibc would still use ld.so.cache, musl has none
+    if is_strict && loader.is_glibc && !loader.inhibit_cache {
+        eprintln!("Strict mode: {} does not support --inhibit-cache", interpreter.display());
+        exit(1)
+    }
+
     cfg_if! {
         if #[cfg(feature = "pyinstaller")] {
             let is_pyinstaller_elf = is_elf_section(&elf_bytes, "pydata").unwrap_or(false);
@@ -1086,76 +2195,173 @@ fn main() {
         }
     }
 
-    let mut interpreter_args: Vec<CString> = Vec::new();
-    if !is_pyinstaller_elf || is_pyinstaller_dir || is_elf32_bin {
+    let elf_bytes = if is_pyinstaller_elf { elf_bytes } else { vec![] };
+
+    // userland exec can only map the interpreter on x86_64 and aarch64
+    if is_elf32_bin || is_foreign_bin ||
+        cfg!(not(any(target_arch = "x86_64", target_arch = "aarch64"))) {
+        exec_strategy = ExecStrategy::Kernel
+    }
+    let is_kernel_exec = exec_strategy == ExecStrategy::Kernel;
+
+    let mut interpreter_args: Vec<OsString> = Vec::new();
+    if !is_pyinstaller_elf || is_pyinstaller_dir || is_kernel_exec {
+        interpreter_args.push(interpreter.clone().into());
+
         interpreter_args.append(&mut vec![
-            CString::from_str(&interpreter.to_string_lossy()).unwrap_or_default(),
-            CString::new("--library-path").unwrap_or_default(),
-            CString::new(&*library_path).unwrap_or_default(),
-            CString::new("--argv0").unwrap_or_default()
+            "--library-path".into(),
+            library_path.as_str().into()
         ]);
 
-        if is_pyinstaller_elf || is_elf32_bin {
-            interpreter_args.push(CString::new(&*bin).unwrap_or_default())
-        } else {
-            interpreter_args.push(CString::new(arg0_path.to_str().unwrap_or_default()).unwrap_or_default())
+        if is_strict && loader.inhibit_cache {
+            interpreter_args.push("--inhibit-cache".into())
         }
 
+        if loader.argv0 {
+            interpreter_args.push("--argv0".into());
+            if is_pyinstaller_elf || is_kernel_exec {
+                interpreter_args.push(bin.as_str().into())
+            } else {
+                interpreter_args.push(arg0_path.clone().into())
+            }
+        }
+
+        let mut preload: Vec<String> = vec![];
+
+        // the built-in library is built for the host, so it is only usable by native binaries
+        #[cfg(feature = "preload")]
+        {
+            let pathmap = read_pathmap(&sharun_dir);
+            let is_preload_exe = get_env_var("SHARUN_PRELOAD_EXE") == "1";
+            if (is_preload_exe || !pathmap.is_empty()) && !is_elf32_bin && !is_foreign_bin {
+                let preload_libs = get_preload_deps(&library_path, &sharun_dir).and_then(|mut preload_libs| {
+                    preload_libs.push(get_preload_lib()?.to_string_lossy().into());
+                    Ok(preload_libs)
+                });
+                match preload_libs {
+                    Ok(mut preload_libs) => {
+                        preload.append(&mut preload_libs);
+                        if is_preload_exe {
+                            env::set_var("SHARUN_EXE", &bin)
+                        }
+                        if !pathmap.is_empty() {
+                            env::set_var("SHARUN_PATHMAP", pathmap.join("\n"))
+                        }
+                    }
+                    Err(err) => eprintln!("Skipping the preload library: {err}")
+                }
+            } else if !pathmap.is_empty() && (is_elf32_bin || is_foreign_bin) {
+                eprintln!("Ignoring .pathmap: the preload library can not be loaded into {bin}: it is {}",
+                    describe_elf(&bin_info))
+            }
+        }
+        #[cfg(not(feature = "preload"))]
+        if Path::new(&format!("{sharun_dir}/.pathmap")).exists() {
+            eprintln!("Ignoring .pathmap: sharun was built without preload support")
+        }
+        env::remove_var("SHARUN_PRELOAD_EXE");
+
         let preload_path = PathBuf::from(format!("{sharun_dir}/.preload"));
         if preload_path.exists() {
             let data = read_to_string(&preload_path).unwrap_or_else(|err|{
                 eprintln!("Failed to read .preload file: {}: {err}", preload_path.display());
                 exit(1)
             });
-            let mut preload: Vec<String> = vec![];
             for string in data.trim().split("\n") {
                 preload.push(string.trim().into());
             }
-            if !preload.is_empty() {
+        }
+
+        if !preload.is_empty() {
+            if loader.preload {
                 interpreter_args.append(&mut vec![
-                    CString::new("--preload").unwrap_or_default(),
-                    CString::new(preload.join(" ")).unwrap_or_default()
+                    "--preload".into(),
+                    preload.join(" ").into()
                 ])
+            } else {
+                let ld_preload_env = get_env_var("LD_PRELOAD");
+                if !ld_preload_env.is_empty() {
+                    preload.push(ld_preload_env)
+                }
+                env::set_var("LD_PRELOAD", preload.join(" "))
             }
         }
 
-        interpreter_args.push(CString::new(&*bin).unwrap_or_default());
-        for arg in &exec_args {
-            interpreter_args.push(CString::from_str(arg).unwrap_or_default())
+        #[cfg(feature = "audit")]
+        if get_env_var("SHARUN_AUDIT") == "1" && (is_elf32_bin || is_foreign_bin) {
+            eprintln!("Ignoring SHARUN_AUDIT: the audit library can not be loaded into {bin}: it is {}",
+                describe_elf(&bin_info))
+        } else if get_env_var("SHARUN_AUDIT") == "1" {
+            if !loader.is_glibc {
+                eprintln!("Ignoring SHARUN_AUDIT: the audit interface is not supported by {}", interpreter.display())
+            } else {
+                match get_audit_lib() {
+                    Ok(audit_lib) => {
+                        let bundle_library_path: Vec<&str> = library_path.split(":")
+                            .filter(|dir| dir.starts_with(&sharun_dir)).collect();
+                        env::set_var("SHARUN_AUDIT_LIBRARY_PATH", bundle_library_path.join(":"));
+                        let audit_lib = audit_lib.to_string_lossy();
+                        if loader.audit {
+                            interpreter_args.append(&mut vec![
+                                "--audit".into(),
+                                audit_lib.as_ref().into()
+                            ])
+                        } else {
+                            // the audit library restores it, so the child processes are not audited
+                            let ld_audit_env = get_env_var("LD_AUDIT");
+                            env::set_var("SHARUN_AUDIT_LD_AUDIT", &ld_audit_env);
+                            if ld_audit_env.is_empty() {
+                                env::set_var("LD_AUDIT", &*audit_lib)
+                            } else {
+                                env::set_var("LD_AUDIT", format!("{audit_lib}:{ld_audit_env}"))
+                            }
+                        }
+                    }
+                    Err(err) => eprintln!("Failed to extract the audit library: {err}")
+                }
+            }
         }
+        env::remove_var("SHARUN_AUDIT");
+
+        interpreter_args.push(bin.as_str().into());
+        interpreter_args.append(&mut exec_args.clone());
     }
 
-    if is_pyinstaller_elf || is_elf32_bin {
-        let err = if is_pyinstaller_dir || (!is_pyinstaller_elf && is_elf32_bin) {
-            drop(elf_bytes);
-            let interpreter_args: Vec<String> = interpreter_args.iter()
-                .map(|s| s.clone().into_string().unwrap_or_default()).skip(1).collect();
-            Command::new(interpreter)
-                .args(interpreter_args)
-                .exec()
-        } else {
-            set_interp(elf_bytes, &bin, interpreter.to_str().unwrap_or_default())
-                .unwrap_or_else(|err|{
-                    eprintln!("Failed to set ELF interpreter: {}: {err}", &bin);
-                    exit(1)
-            });
-            Command::new(&bin)
-                .args(exec_args)
-                .exec()
-        };
-        eprint!("Failed to exec: {bin}: {err}");
-        exit(1)
-    } else {
-        drop(elf_bytes);
-        let envs: Vec<CString> = env::vars_os()
-            .map(|(key, value)| CString::new(
-                format!("{}={}", key.to_string_lossy(), value.to_string_lossy())
-        ).unwrap_or_default()).collect();
+    #[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), allow(unused_variables))]
+    let is_printenv = print_env();
 
-        userland_execve::exec(
-            interpreter.as_path(),
-            &interpreter_args,
-            &envs,
-        )
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    if !is_pyinstaller_elf && !is_kernel_exec {
+        let Err(err) = to_cstrings(&interpreter_args).and_then(|interpreter_args| {
+            let envs = get_envs()?;
+            let execfn = to_cstring(&bin)?;
+            set_process_name(&bin_name);
+            userland_exec::exec(interpreter.as_path(), &interpreter_args, &envs, &execfn)
+        });
+        if exec_strategy == ExecStrategy::Userland {
+            eprintln!("Failed to exec: {bin}: {err}");
+            exit(1)
+        }
+        // SELinux, PaX or seccomp may deny the mappings, the kernel can still load the interpreter
+        if is_printenv {
+            eprintln!("Userland exec failed: {err}, using the kernel exec")
+        }
+        if let Some(argv0) = interpreter_args.iter().position(|arg| arg == "--argv0") {
+            interpreter_args[argv0 + 1] = bin.as_str().into()
+        }
     }
+
+    let err = if let Some(emulator) = emulator {
+        exec_emulator(&emulator, shared_dir, &library_path, &interpreter_args, &bin, &exec_args)
+    } else if is_pyinstaller_dir || !is_pyinstaller_elf {
+        drop(elf_bytes);
+        Command::new(interpreter)
+            .args(&interpreter_args[1..])
+            .exec()
+    } else {
+        let Err(err) = exec_with_interp(&elf_bytes, &bin, &interpreter, &exec_args);
+        err
+    };
+    eprintln!("Failed to exec: {bin}: {err}");
+    exit(1)
 }
@@ -0,0 +1,180 @@
+// Integrity manifest of the bundle: a '.manifest' file in the sharun dir with a line
+// '<sha256> <mode> <size> <mtime> <path>' for every file and symlink, paths are relative.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    ffi::OsStr,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    io::{Result, Error, ErrorKind::InvalidData}
+};
+
+use nix::libc;
+
+
+pub const MANIFEST_NAME: &str = ".manifest";
+#[cfg(feature = "verify")]
+pub const SIGNATURE_NAME: &str = ".manifest.sig";
+#[cfg(feature = "verify")]
+const MANIFEST_HEADER: &str = "# sharun manifest v1";
+
+pub struct Entry {
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    pub sha256: String,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: i64
+}
+
+#[cfg(feature = "verify")]
+pub fn escape_path(path: &Path) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'\\' => escaped.extend(b"\\\\"),
+            b'\n' => escaped.extend(b"\\n"),
+            byte => escaped.push(byte)
+        }
+    }
+    escaped
+}
+
+fn unescape_path(path: &[u8]) -> PathBuf {
+    let mut unescaped = Vec::new();
+    let mut bytes = path.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        match (byte, bytes.peek()) {
+            (b'\\', Some(b'\\')) => { bytes.next(); unescaped.push(b'\\') }
+            (b'\\', Some(b'n')) => { bytes.next(); unescaped.push(b'\n') }
+            (byte, _) => unescaped.push(byte)
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&unescaped))
+}
+
+// relative paths of the files and symlinks of the bundle, in the order of their names
+#[cfg(feature = "verify")]
+pub fn get_bundle_files(sharun_dir: &str) -> Vec<PathBuf> {
+    use walkdir::WalkDir;
+
+    WalkDir::new(sharun_dir).sort_by_file_name().into_iter().flatten()
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| entry.path().strip_prefix(sharun_dir).ok().map(PathBuf::from))
+        .filter(|path| path != Path::new(MANIFEST_NAME) && path != Path::new(SIGNATURE_NAME))
+        .collect()
+}
+
+pub fn read_manifest(sharun_dir: &str) -> Result<Vec<(PathBuf, Entry)>> {
+    read_manifest_file(&Path::new(sharun_dir).join(MANIFEST_NAME))
+}
+
+pub fn read_manifest_file(manifest_path: &Path) -> Result<Vec<(PathBuf, Entry)>> {
+    let data = fs::read(manifest_path).map_err(|err|
+        Error::new(err.kind(), format!("{}: {err}", manifest_path.display())))?;
+    let invalid = |line_num: usize| Error::new(InvalidData,
+        format!("{}:{line_num}: invalid line", manifest_path.display()));
+    let mut entries = Vec::new();
+    for (line_num, line) in data.split(|&byte| byte == b'\n').enumerate() {
+        if line.is_empty() || line.starts_with(b"#") {
+            continue
+        }
+        let fields: Vec<&[u8]> = line.splitn(5, |&byte| byte == b' ').collect();
+        let [sha256, mode, size, mtime, path] = fields[..] else {
+            return Err(invalid(line_num + 1))
+        };
+        let field = |field: &[u8]| String::from_utf8_lossy(field).to_string();
+        let entry = Entry {
+            sha256: field(sha256),
+            mode: u32::from_str_radix(&field(mode), 8).map_err(|_| invalid(line_num + 1))?,
+            size: field(size).parse().map_err(|_| invalid(line_num + 1))?,
+            mtime: field(mtime).parse().map_err(|_| invalid(line_num + 1))?
+        };
+        entries.push((unescape_path(path), entry))
+    }
+    Ok(entries)
+}
+
+// size and mtime only, so it is cheap enough to run on every launch
+pub fn check_quick(sharun_dir: &str) -> Result<Vec<String>> {
+    Ok(check_entries(Path::new(sharun_dir), read_manifest(sharun_dir)?))
+}
+
+pub fn check_entries(dir: &Path, entries: Vec<(PathBuf, Entry)>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (path, entry) in entries {
+        let full_path = dir.join(&path);
+        let Ok(metadata) = full_path.symlink_metadata() else {
+            problems.push(format!("missing: {}", path.display()));
+            continue
+        };
+        let is_modified = if metadata.is_symlink() {
+            entry.mode & libc::S_IFMT != libc::S_IFLNK ||
+                !fs::read_link(&full_path).is_ok_and(|target| target.as_os_str().len() as u64 == entry.size)
+        } else {
+            metadata.mode() != entry.mode || metadata.len() != entry.size || metadata.mtime() != entry.mtime
+        };
+        if is_modified {
+            problems.push(format!("modified: {}", path.display()))
+        }
+    }
+    problems
+}
+
+#[cfg(feature = "verify")]
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(feature = "verify")]
+fn get_entry(full_path: &Path) -> Result<Entry> {
+    use sha2::{Sha256, Digest};
+
+    let metadata = full_path.symlink_metadata()?;
+    let mut hasher = Sha256::new();
+    let size = if metadata.is_symlink() {
+        let target = fs::read_link(full_path)?;
+        hasher.update(target.as_os_str().as_bytes());
+        target.as_os_str().len() as u64
+    } else {
+        std::io::copy(&mut fs::File::open(full_path)?, &mut hasher)?
+    };
+    let sha256 = to_hex(&hasher.finalize());
+    Ok(Entry { sha256, mode: metadata.mode(), size, mtime: metadata.mtime() })
+}
+
+#[cfg(feature = "verify")]
+pub fn create_manifest(sharun_dir: &str) -> Result<usize> {
+    let files = get_bundle_files(sharun_dir);
+    let mut data = format!("{MANIFEST_HEADER}\n").into_bytes();
+    for path in &files {
+        let entry = get_entry(&Path::new(sharun_dir).join(path))?;
+        data.extend(format!("{} {:o} {} {} ", entry.sha256, entry.mode, entry.size, entry.mtime).as_bytes());
+        data.extend(escape_path(path));
+        data.push(b'\n')
+    }
+    fs::write(Path::new(sharun_dir).join(MANIFEST_NAME), data)?;
+    Ok(files.len())
+}
+
+// returns the missing and modified files, and the extra files separately
+#[cfg(feature = "verify")]
+pub fn verify(sharun_dir: &str) -> Result<(Vec<String>, Vec<PathBuf>)> {
+    use std::{collections::HashMap, io::ErrorKind::NotFound};
+
+    let entries = read_manifest(sharun_dir)?;
+    let mut problems = Vec::new();
+    for (path, entry) in &entries {
+        match get_entry(&Path::new(sharun_dir).join(path)) {
+            Ok(file_entry) if file_entry.sha256 == entry.sha256 && file_entry.size == entry.size &&
+                file_entry.mode == entry.mode => {}
+            Ok(_) => problems.push(format!("modified: {}", path.display())),
+            Err(err) if err.kind() == NotFound => problems.push(format!("missing: {}", path.display())),
+            Err(err) => problems.push(format!("unreadable: {}: {err}", path.display()))
+        }
+    }
+    let entries: HashMap<PathBuf, Entry> = entries.into_iter().collect();
+    let extra = get_bundle_files(sharun_dir).into_iter()
+        .filter(|path| !entries.contains_key(path))
+        .collect();
+    Ok((problems, extra))
+}
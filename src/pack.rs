@@ -0,0 +1,334 @@
+// Single-file bundles: a copy of sharun with the deflate compressed archive of the bundle dir
+// and a trailer '<payload offset> <payload size> <sha256 of payload> SHARUNPK' appended.
+// The payload is checked against its hash and extracted once into a cache dir named by it.
+
+use std::{
+    env,
+    process,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    time::{Duration, UNIX_EPOCH},
+    path::{Component, Path, PathBuf},
+    fs::{self, File, OpenOptions},
+    os::{fd::AsRawFd, unix::{ffi::OsStrExt, fs::{symlink, MetadataExt, OpenOptionsExt, PermissionsExt}}},
+    io::{self, Read, Seek, SeekFrom, Write, BufReader, BufWriter, Result, Error, ErrorKind::{InvalidData, InvalidInput, NotFound}}
+};
+
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use nix::libc;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{SHARUN_NAME, get_env_var, is_exe, manifest::{escape_path, read_manifest_file, check_entries, to_hex}};
+
+
+const MAGIC: &[u8; 8] = b"SHARUNPK";
+const TRAILER_SIZE: u64 = 8 + 8 + 32 + 8;
+const MAX_PATH_LEN: u64 = 4096;
+
+const ENTRY_FILE: u8 = 0;
+const ENTRY_DIR: u8 = 1;
+const ENTRY_SYMLINK: u8 = 2;
+const ENTRY_HARDLINK: u8 = 3;
+const ENTRY_END: u8 = 0xff;
+
+pub struct Payload {
+    offset: u64,
+    size: u64,
+    hash: [u8; 32]
+}
+
+impl Payload {
+    pub fn find(exe: &Path) -> Option<Self> {
+        let mut file = File::open(exe).ok()?;
+        let len = file.metadata().ok()?.len();
+        if len < TRAILER_SIZE {
+            return None
+        }
+        file.seek(SeekFrom::End(-(TRAILER_SIZE as i64))).ok()?;
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        file.read_exact(&mut trailer).ok()?;
+        if &trailer[48..] != MAGIC {
+            return None
+        }
+        let offset = u64::from_le_bytes(trailer[..8].try_into().ok()?);
+        let size = u64::from_le_bytes(trailer[8..16].try_into().ok()?);
+        if offset.checked_add(size)?.checked_add(TRAILER_SIZE)? != len {
+            return None
+        }
+        Some(Self { offset, size, hash: trailer[16..48].try_into().ok()? })
+    }
+}
+
+fn write_entry(writer: &mut impl Write, kind: u8, mode: u32, mtime: i64, path: &Path, data_len: u64) -> Result<()> {
+    let path = path.as_os_str().as_bytes();
+    writer.write_all(&[kind])?;
+    writer.write_all(&mode.to_le_bytes())?;
+    writer.write_all(&mtime.to_le_bytes())?;
+    writer.write_all(&(path.len() as u64).to_le_bytes())?;
+    writer.write_all(path)?;
+    writer.write_all(&data_len.to_le_bytes())
+}
+
+fn write_archive(dir: &Path, skipped: &[&Path], writer: &mut impl Write) -> Result<()> {
+    // the bins are often hard links to sharun, store them once
+    let mut inodes: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for entry in WalkDir::new(dir).sort_by_file_name().min_depth(1) {
+        let entry = entry?;
+        if skipped.contains(&entry.path()) {
+            continue
+        }
+        let metadata = entry.metadata()?;
+        let path = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        let (mode, mtime) = (metadata.mode(), metadata.mtime());
+        if metadata.is_dir() {
+            write_entry(writer, ENTRY_DIR, mode, mtime, path, 0)?
+        } else if metadata.is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            let target = target.as_os_str().as_bytes();
+            write_entry(writer, ENTRY_SYMLINK, mode, mtime, path, target.len() as u64)?;
+            writer.write_all(target)?
+        } else if metadata.is_file() {
+            if let Some(target) = inodes.get(&(metadata.dev(), metadata.ino())) {
+                let target = target.as_os_str().as_bytes();
+                write_entry(writer, ENTRY_HARDLINK, mode, mtime, path, target.len() as u64)?;
+                writer.write_all(target)?;
+                continue
+            }
+            if metadata.nlink() > 1 {
+                inodes.insert((metadata.dev(), metadata.ino()), path.into());
+            }
+            write_entry(writer, ENTRY_FILE, mode, mtime, path, metadata.len())?;
+            let copied = io::copy(&mut File::open(entry.path())?.take(metadata.len()), writer)?;
+            if copied != metadata.len() {
+                return Err(Error::new(InvalidData, format!("{}: file changed while packing", entry.path().display())))
+            }
+        }
+    }
+    writer.write_all(&[ENTRY_END])
+}
+
+pub fn pack(dir: &Path, sharun: &Path, output: &Path) -> Result<()> {
+    let dir = dir.canonicalize()?;
+    if !is_exe(&dir.join(SHARUN_NAME)) {
+        return Err(Error::new(NotFound, format!("'{}' is not a sharun dir: no {SHARUN_NAME} in it", dir.display())))
+    }
+    // a packed sharun is copied without its own payload
+    let sharun_len = match Payload::find(sharun) {
+        Some(payload) => payload.offset,
+        None => sharun.metadata()?.len()
+    };
+    let file_name = output.file_name()
+        .ok_or_else(|| Error::new(InvalidInput, format!("invalid output file: {}", output.display())))?;
+    let output_dir = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => env::current_dir()?
+    };
+    let output = output_dir.join(file_name);
+    // renamed over the output, which may be sharun itself or a hard link to it
+    let tmp_output = output_dir.join(format!(".{}.tmp{}", file_name.to_string_lossy(), process::id()));
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).mode(0o755).open(&tmp_output)?;
+    write_packed(&mut file, &dir, sharun, sharun_len, &[&output, &tmp_output])
+        .and_then(|_| fs::rename(&tmp_output, &output))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_output);
+        })
+}
+
+fn write_packed(file: &mut File, dir: &Path, sharun: &Path, sharun_len: u64, skipped: &[&Path]) -> Result<()> {
+    io::copy(&mut File::open(sharun)?.take(sharun_len), file)?;
+
+    let offset = file.stream_position()?;
+    let mut encoder = DeflateEncoder::new(BufWriter::new(&*file), Compression::best());
+    write_archive(dir, skipped, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    let size = file.stream_position()? - offset;
+
+    let mut hasher = Sha256::new();
+    file.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut (&*file).take(size), &mut hasher)?;
+    file.write_all(&offset.to_le_bytes())?;
+    file.write_all(&size.to_le_bytes())?;
+    file.write_all(&hasher.finalize())?;
+    file.write_all(MAGIC)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_path(reader: &mut impl Read, len: u64) -> Result<PathBuf> {
+    if len > MAX_PATH_LEN {
+        return Err(Error::new(InvalidData, "invalid path length in payload"))
+    }
+    let mut path = vec![0u8; len as usize];
+    reader.read_exact(&mut path)?;
+    Ok(PathBuf::from(OsStr::from_bytes(&path)))
+}
+
+// only relative paths without '..', so nothing is written outside of the dest dir,
+// nor through a symlink from the payload
+fn check_path<'a>(path: &'a Path, symlinks: &HashSet<PathBuf>) -> Result<&'a Path> {
+    if !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(Error::new(InvalidData, format!("invalid path in payload: {}", path.display())))
+    }
+    if path.ancestors().skip(1).any(|parent| symlinks.contains(parent)) {
+        return Err(Error::new(InvalidData, format!("path through a symlink in payload: {}", path.display())))
+    }
+    Ok(path)
+}
+
+// the payload is read from the same file it was hashed from
+fn open_payload(exe: &Path, payload: &Payload) -> Result<File> {
+    let mut file = File::open(exe)?;
+    file.seek(SeekFrom::Start(payload.offset))?;
+    let mut hasher = Sha256::new();
+    if io::copy(&mut (&file).take(payload.size), &mut hasher)? != payload.size {
+        return Err(Error::new(InvalidData, "truncated payload"))
+    }
+    if hasher.finalize()[..] != payload.hash {
+        return Err(Error::new(InvalidData, "the payload hash does not match, the file is damaged"))
+    }
+    file.seek(SeekFrom::Start(payload.offset))?;
+    Ok(file)
+}
+
+pub fn extract(exe: &Path, payload: &Payload, dest: &Path) -> Result<()> {
+    let file = open_payload(exe, payload)?;
+    let mut reader = BufReader::new(DeflateDecoder::new(BufReader::new(file.take(payload.size))));
+    fs::create_dir(dest)?;
+    let mut dirs = Vec::new();
+    let mut paths = HashSet::new();
+    let mut files = HashSet::new();
+    let mut symlinks = HashSet::new();
+    loop {
+        let [kind] = read_array(&mut reader)?;
+        if kind == ENTRY_END {
+            break
+        }
+        let mode = u32::from_le_bytes(read_array(&mut reader)?);
+        let mtime = i64::from_le_bytes(read_array(&mut reader)?);
+        let path_len = u64::from_le_bytes(read_array(&mut reader)?);
+        let path = read_path(&mut reader, path_len)?;
+        let data_len = u64::from_le_bytes(read_array(&mut reader)?);
+        let full_path = dest.join(check_path(&path, &symlinks)?);
+        if !paths.insert(path.clone()) {
+            return Err(Error::new(InvalidData, format!("duplicate path in payload: {}", path.display())))
+        }
+        match kind {
+            ENTRY_DIR => {
+                fs::create_dir(&full_path)?;
+                dirs.push((full_path, mode))
+            }
+            ENTRY_FILE => {
+                let mut file = OpenOptions::new().write(true).create_new(true).open(&full_path)?;
+                if io::copy(&mut (&mut reader).take(data_len), &mut file)? != data_len {
+                    return Err(Error::new(InvalidData, "truncated payload"))
+                }
+                file.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+                file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64))?;
+                files.insert(path);
+            }
+            ENTRY_SYMLINK => {
+                symlink(read_path(&mut reader, data_len)?, &full_path)?;
+                symlinks.insert(path);
+            }
+            // only to a file extracted before
+            ENTRY_HARDLINK => {
+                let target = read_path(&mut reader, data_len)?;
+                if !files.contains(check_path(&target, &symlinks)?) {
+                    return Err(Error::new(InvalidData, format!("invalid hard link in payload: {}", path.display())))
+                }
+                fs::hard_link(dest.join(&target), &full_path)?
+            }
+            _ => return Err(Error::new(InvalidData, "invalid entry in payload"))
+        }
+    }
+    // after their files, a read-only dir would not let them be created
+    for (dir, mode) in dirs.into_iter().rev() {
+        fs::set_permissions(dir, fs::Permissions::from_mode(mode & 0o7777))?
+    }
+    Ok(())
+}
+
+// '# <payload hash>' and the manifest lines without the sha256 of the extracted files,
+// written once the dir is complete and checked by size and mtime on every launch
+fn write_marker(dir: &Path, marker: &Path, hash: &str) -> Result<()> {
+    let mut data = format!("# {hash}\n").into_bytes();
+    for entry in WalkDir::new(dir).sort_by_file_name().min_depth(1) {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            continue
+        }
+        let size = if metadata.is_symlink() {
+            fs::read_link(entry.path())?.as_os_str().len() as u64
+        } else { metadata.len() };
+        data.extend(format!("- {:o} {size} {} ", metadata.mode(), metadata.mtime()).as_bytes());
+        data.extend(escape_path(entry.path().strip_prefix(dir).unwrap_or(entry.path())));
+        data.push(b'\n')
+    }
+    fs::write(marker, data)
+}
+
+fn is_complete(dir: &Path, marker: &Path, hash: &str) -> bool {
+    fs::read(marker).is_ok_and(|data| data.starts_with(format!("# {hash}\n").as_bytes())) &&
+        read_manifest_file(marker).is_ok_and(|entries| check_entries(dir, entries).is_empty())
+}
+
+// the extracted dirs may be read-only
+fn remove_extracted(dir: &Path) -> Result<()> {
+    for entry in WalkDir::new(dir).into_iter().flatten() {
+        if entry.file_type().is_dir() {
+            let _ = fs::set_permissions(entry.path(), fs::Permissions::from_mode(0o700));
+        }
+    }
+    fs::remove_dir_all(dir)
+}
+
+pub fn unpack_cached(exe: &Path, payload: &Payload) -> Result<PathBuf> {
+    let cache_home = match env::var("XDG_CACHE_HOME") {
+        Ok(cache_home) if !cache_home.is_empty() => cache_home,
+        _ => format!("{}/.cache", get_env_var("HOME"))
+    };
+    let packed_dir = PathBuf::from(format!("{cache_home}/{SHARUN_NAME}/packed"));
+    let hash = to_hex(&payload.hash);
+    let name = &hash[..32];
+    let dir = packed_dir.join(name);
+    let marker = packed_dir.join(format!(".{name}.done"));
+    if is_complete(&dir, &marker, &hash) {
+        return Ok(dir)
+    }
+
+    fs::create_dir_all(&packed_dir)?;
+    let lock_file = File::create(packed_dir.join(format!(".{name}.lock")))?;
+    if unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(Error::last_os_error())
+    }
+    // extracted by another process while we were waiting for the lock
+    if is_complete(&dir, &marker, &hash) {
+        return Ok(dir)
+    }
+    // left by an interrupted extraction, or changed since
+    let _ = fs::remove_file(&marker);
+    match dir.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => remove_extracted(&dir)?,
+        Ok(_) => fs::remove_file(&dir)?,
+        Err(_) => {}
+    }
+    for entry in packed_dir.read_dir()?.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&format!(".{name}.tmp")) {
+            let _ = remove_extracted(&entry.path());
+        }
+    }
+    let tmp_dir = packed_dir.join(format!(".{name}.tmp{}", process::id()));
+    if let Err(err) = extract(exe, payload, &tmp_dir) {
+        let _ = remove_extracted(&tmp_dir);
+        return Err(err)
+    }
+    fs::rename(&tmp_dir, &dir)?;
+    write_marker(&dir, &marker, &hash)?;
+    Ok(dir)
+}
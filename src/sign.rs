@@ -0,0 +1,206 @@
+// Ed25519 signature of the bundle manifest, and the launch policy that refuses unsigned
+// or tampered bundles if trusted keys are configured on the host.
+
+use std::{
+    env,
+    collections::HashSet,
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    io::{Result, Error, ErrorKind::{InvalidData, NotFound}}
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::{SHARUN_NAME, get_env_var, get_library_path_data, manifest::{self, MANIFEST_NAME, SIGNATURE_NAME, to_hex}};
+
+
+const TRUSTED_KEYS_NAME: &str = "trusted-keys";
+// DER headers of the keys from 'openssl genpkey -algorithm ed25519' and 'openssl pkey -pubout'
+const PKCS8_PREFIX: [u8; 16] = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+const SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn decode_base64(base64: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for byte in base64.bytes().filter(|&byte| byte != b'=' && !byte.is_ascii_whitespace()) {
+        let val = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+        acc = ((acc << 6) | val as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8)
+        }
+    }
+    Some(bytes)
+}
+
+// a raw 32 byte key, a hex string or a PEM file
+fn read_key(path: &Path, der_prefix: &[u8]) -> Result<[u8; 32]> {
+    let data = fs::read(path).map_err(|err| Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+    let invalid = || Error::new(InvalidData, format!("{}: not an Ed25519 key", path.display()));
+    let key = if data.len() == 32 {
+        data
+    } else {
+        let text = String::from_utf8(data).map_err(|_| invalid())?;
+        let text = text.trim();
+        if text.starts_with("-----BEGIN ") {
+            let base64: String = text.lines().filter(|line| !line.starts_with("-----")).collect();
+            let der = decode_base64(&base64).ok_or_else(invalid)?;
+            der.strip_prefix(der_prefix).ok_or_else(invalid)?.to_vec()
+        } else {
+            decode_hex(text).ok_or_else(invalid)?
+        }
+    };
+    key.try_into().map_err(|_| invalid())
+}
+
+fn read_trusted_keys(path: &Path) -> Result<Vec<VerifyingKey>> {
+    let data = read_to_string(path)?;
+    let mut keys = Vec::new();
+    for (line_num, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let key = decode_hex(line).and_then(|key| key.try_into().ok())
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .ok_or_else(|| Error::new(InvalidData,
+                format!("{}:{}: not an Ed25519 public key", path.display(), line_num + 1)))?;
+        keys.push(key)
+    }
+    Ok(keys)
+}
+
+// only the keys of the host: a key shipped with the bundle would prove nothing,
+// anyone who changes the bundle can replace it too
+fn get_trusted_keys() -> Result<Option<Vec<VerifyingKey>>> {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(config_home) if !config_home.is_empty() => config_home,
+        _ => format!("{}/.config", get_env_var("HOME"))
+    };
+    let mut trusted_keys = None;
+    for path in [
+        PathBuf::from(format!("/etc/{SHARUN_NAME}/{TRUSTED_KEYS_NAME}")),
+        PathBuf::from(format!("{config_home}/{SHARUN_NAME}/{TRUSTED_KEYS_NAME}"))
+    ] {
+        match read_trusted_keys(&path) {
+            Ok(keys) => trusted_keys.get_or_insert_with(Vec::new).extend(keys),
+            Err(err) if err.kind() == NotFound => {}
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(trusted_keys)
+}
+
+// lib.path is generated on the first launch or by '-g', so it is not tampering
+// if it lists the same dirs as the generated one would
+fn is_generated(sharun_dir: &str, path: &Path) -> bool {
+    if !["shared/lib/lib.path", "shared/lib32/lib.path"].iter().any(|lib_path| path == Path::new(lib_path)) {
+        return false
+    }
+    let library_path = Path::new(sharun_dir).join(path.parent().unwrap_or(Path::new("")));
+    let (Some(library_path), Ok(data)) = (library_path.to_str(), read_to_string(Path::new(sharun_dir).join(path))) else {
+        return false
+    };
+    let dirs = |data: &str| data.lines().map(String::from).collect::<HashSet<String>>();
+    dirs(&data) == dirs(&get_library_path_data(library_path))
+}
+
+fn verify_signature(sharun_dir: &str, keys: &[VerifyingKey]) -> Result<()> {
+    let read = |name: &str| fs::read(Path::new(sharun_dir).join(name)).map_err(|err| match err.kind() {
+        NotFound => Error::new(NotFound, "the bundle is not signed"),
+        _ => err
+    });
+    let manifest = read(MANIFEST_NAME)?;
+    let signature = String::from_utf8_lossy(&read(SIGNATURE_NAME)?).to_string();
+    let signature = decode_hex(signature.trim()).and_then(|signature| signature.try_into().ok())
+        .map(|signature: [u8; 64]| Signature::from_bytes(&signature))
+        .ok_or_else(|| Error::new(InvalidData, format!("invalid {SIGNATURE_NAME}")))?;
+    if keys.iter().any(|key| key.verify_strict(&manifest, &signature).is_ok()) {
+        Ok(())
+    } else {
+        Err(Error::new(InvalidData, "the signature of the manifest does not match the trusted keys"))
+    }
+}
+
+// the ctime of a file cannot be set from userspace, so it changes with any change of the bundle
+fn get_fingerprint(sharun_dir: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for name in [MANIFEST_NAME, SIGNATURE_NAME] {
+        hasher.update(fs::read(Path::new(sharun_dir).join(name))?)
+    }
+    for path in manifest::get_bundle_files(sharun_dir) {
+        let metadata = Path::new(sharun_dir).join(&path).symlink_metadata()?;
+        hasher.update(path.as_os_str().as_bytes());
+        hasher.update(format!(" {} {} {} {} {} {}\n", metadata.dev(), metadata.ino(),
+            metadata.ctime(), metadata.ctime_nsec(), metadata.size(), metadata.mode()));
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn sign(sharun_dir: &str, key_path: &Path) -> Result<String> {
+    let signing_key = SigningKey::from_bytes(&read_key(key_path, &PKCS8_PREFIX)?);
+    manifest::create_manifest(sharun_dir)?;
+    let manifest = fs::read(Path::new(sharun_dir).join(MANIFEST_NAME))?;
+    let signature = signing_key.sign(&manifest);
+    fs::write(Path::new(sharun_dir).join(SIGNATURE_NAME), format!("{}\n", to_hex(&signature.to_bytes())))?;
+    Ok(to_hex(signing_key.verifying_key().as_bytes()))
+}
+
+pub fn verify_with_pubkey(sharun_dir: &str, pubkey_path: &Path) -> Result<()> {
+    let key = VerifyingKey::from_bytes(&read_key(pubkey_path, &SPKI_PREFIX)?)
+        .map_err(|_| Error::new(InvalidData, format!("{}: not an Ed25519 public key", pubkey_path.display())))?;
+    verify_signature(sharun_dir, &[key])
+}
+
+// the bundle can only opt in to the check of its files with its own .trusted-keys,
+// the signature is checked if the host has trusted keys
+pub fn check_policy(sharun_dir: &str) -> Result<()> {
+    match get_trusted_keys()? {
+        Some(keys) => verify_signature(sharun_dir, &keys)?,
+        None if Path::new(sharun_dir).join(format!(".{TRUSTED_KEYS_NAME}")).exists() => {}
+        None => return Ok(())
+    }
+
+    // hashing the whole bundle on every launch is too slow, the result is cached until any file changes
+    let fingerprint = get_fingerprint(sharun_dir)?;
+    let cache_home = match env::var("XDG_CACHE_HOME") {
+        Ok(cache_home) if !cache_home.is_empty() => cache_home,
+        _ => format!("{}/.cache", get_env_var("HOME"))
+    };
+    let cache_path = PathBuf::from(format!("{cache_home}/{SHARUN_NAME}/verified/{}",
+        &to_hex(&Sha256::digest(sharun_dir.as_bytes()))[..32]));
+    if read_to_string(&cache_path).is_ok_and(|cached| cached == fingerprint) {
+        return Ok(())
+    }
+    let (problems, extra) = manifest::verify(sharun_dir)?;
+    let extra: Vec<String> = extra.into_iter()
+        .filter(|path| !is_generated(sharun_dir, path))
+        .map(|path| format!("extra: {}", path.display()))
+        .collect();
+    if !problems.is_empty() || !extra.is_empty() {
+        return Err(Error::new(InvalidData, problems.into_iter().chain(extra).collect::<Vec<String>>().join(", ")))
+    }
+    if let Some(cache_dir) = cache_path.parent() {
+        if fs::create_dir_all(cache_dir).is_ok() {
+            fs::write(&cache_path, fingerprint).ok();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,331 @@
+// ptrace based discovery of the files a command loads at runtime, like the dlopened libraries
+// and the data files, as a list of '<bin|lib|data> <path>' lines for 'sharun collect --list'.
+
+use std::{
+    thread,
+    sync::{Arc, Mutex, PoisonError},
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    time::Duration,
+    process::Command,
+    path::{Path, PathBuf},
+    collections::{BTreeSet, HashMap, HashSet, hash_map::Entry},
+    os::unix::{ffi::OsStrExt, fs::FileExt, process::CommandExt},
+    io::{Result, Error, ErrorKind::{NotFound, Unsupported}}
+};
+
+use cfg_if::cfg_if;
+
+use nix::{
+    libc,
+    errno::Errno,
+    unistd::Pid,
+    sys::{
+        signal::Signal,
+        ptrace::{self, Event, Options},
+        wait::{waitpid, WaitPidFlag, WaitStatus}
+    }
+};
+
+use crate::{get_elf_info, is_script, which};
+
+
+const SYSCALL_INFO_ENTRY: u8 = 1;
+const SYSCALL_INFO_EXIT: u8 = 2;
+const PATH_MAX: u64 = 4096;
+// the files of the system itself, not of the command
+const SKIP_PREFIXES: [&str; 5] = ["/proc/", "/sys/", "/dev/", "/run/", "/tmp/"];
+const SKIP_FILES: [&str; 2] = ["/etc/ld.so.cache", "/etc/ld.so.preload"];
+const DATA_PREFIXES: [&str; 3] = ["/usr/share/", "/usr/local/share/", "/etc/"];
+
+// AUDIT_ARCH_* of linux/audit.h, the 32-bit processes on a 64-bit host have another arch
+// and other syscall numbers: only openat, readlinkat and execveat differ between i386 and arm
+cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const AUDIT_ARCH: u32 = 0xc000003e;
+        const COMPAT_AUDIT_ARCH: u32 = 0x40000003;
+        const COMPAT_AT_SYSCALLS: [i64; 3] = [295, 305, 358];
+    } else if #[cfg(target_arch = "x86")] {
+        const AUDIT_ARCH: u32 = 0x40000003;
+    } else if #[cfg(target_arch = "aarch64")] {
+        const AUDIT_ARCH: u32 = 0xc00000b7;
+        const COMPAT_AUDIT_ARCH: u32 = 0x40000028;
+        const COMPAT_AT_SYSCALLS: [i64; 3] = [322, 332, 387];
+    } else if #[cfg(target_arch = "arm")] {
+        const AUDIT_ARCH: u32 = 0x40000028;
+    } else if #[cfg(target_arch = "riscv64")] {
+        const AUDIT_ARCH: u32 = 0xc00000f3;
+    } else if #[cfg(target_arch = "powerpc64")] {
+        const AUDIT_ARCH: u32 = 0xc0000015;
+    } else if #[cfg(target_arch = "s390x")] {
+        const AUDIT_ARCH: u32 = 0x80000016;
+    } else if #[cfg(target_arch = "loongarch64")] {
+        const AUDIT_ARCH: u32 = 0xc0000102;
+    }
+}
+
+// struct ptrace_syscall_info of linux/ptrace.h, libc has it only for glibc
+#[repr(C)]
+struct SyscallInfo {
+    op: u8,
+    _pad: [u8; 3],
+    arch: u32,
+    _instruction_pointer: u64,
+    _stack_pointer: u64,
+    // entry: nr, args[6]; exit: rval, is_error
+    data: [u64; 8]
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Access {
+    Open,
+    Exec,
+    // the link is recorded with the file it points to, which is what the command uses
+    Readlink
+}
+
+fn get_syscall_info(pid: Pid) -> Result<SyscallInfo> {
+    let mut info: SyscallInfo = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::ptrace(libc::PTRACE_GET_SYSCALL_INFO as _, pid.as_raw(),
+            std::mem::size_of::<SyscallInfo>(), &mut info as *mut SyscallInfo)
+    };
+    if ret <= 0 {
+        return Err(Error::last_os_error())
+    }
+    Ok(info)
+}
+
+// the path argument, made absolute while the dirfd and the cwd of the process are still valid
+fn get_path_arg(pid: Pid, mem: &File, dirfd: Option<u64>, addr: u64) -> Option<PathBuf> {
+    let mut path = Vec::new();
+    let mut addr = addr;
+    // page by page, the string can end right before an unmapped page
+    while (path.len() as u64) < PATH_MAX {
+        let mut chunk = vec![0u8; (4096 - addr % 4096) as usize];
+        let len = mem.read_at(&mut chunk, addr).ok().filter(|&len| len > 0)?;
+        match chunk[..len].iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                path.extend_from_slice(&chunk[..end]);
+                break
+            }
+            None => path.extend_from_slice(&chunk[..len])
+        }
+        addr += len as u64
+    }
+    let path = PathBuf::from(OsStr::from_bytes(&path));
+    if path.as_os_str().is_empty() || path.is_absolute() {
+        return Some(path).filter(|path| path.is_absolute())
+    }
+    let dir = match dirfd.map(|dirfd| dirfd as i32) {
+        Some(dirfd) if dirfd != libc::AT_FDCWD => fs::read_link(format!("/proc/{pid}/fd/{dirfd}")),
+        _ => fs::read_link(format!("/proc/{pid}/cwd"))
+    };
+    dir.ok().map(|dir| dir.join(path))
+}
+
+// the syscalls that take a path of a file to load: (path arg, dirfd arg), access
+fn get_native_path_syscall(nr: i64) -> Option<(usize, Option<usize>, Access)> {
+    match nr {
+        libc::SYS_openat | libc::SYS_openat2 => Some((1, Some(0), Access::Open)),
+        libc::SYS_readlinkat => Some((1, Some(0), Access::Readlink)),
+        libc::SYS_execveat => Some((1, Some(0), Access::Exec)),
+        libc::SYS_execve => Some((0, None, Access::Exec)),
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "arm",
+            target_arch = "powerpc64", target_arch = "s390x"))]
+        libc::SYS_open => Some((0, None, Access::Open)),
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "arm",
+            target_arch = "powerpc64", target_arch = "s390x"))]
+        libc::SYS_readlink => Some((0, None, Access::Readlink)),
+        _ => None
+    }
+}
+
+// open, execve and readlink have the same numbers on i386 and arm, openat2 is the same everywhere
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn get_compat_path_syscall(nr: i64) -> Option<(usize, Option<usize>, Access)> {
+    match nr {
+        5 => Some((0, None, Access::Open)),
+        85 => Some((0, None, Access::Readlink)),
+        11 => Some((0, None, Access::Exec)),
+        437 => Some((1, Some(0), Access::Open)),
+        nr if nr == COMPAT_AT_SYSCALLS[0] => Some((1, Some(0), Access::Open)),
+        nr if nr == COMPAT_AT_SYSCALLS[1] => Some((1, Some(0), Access::Readlink)),
+        nr if nr == COMPAT_AT_SYSCALLS[2] => Some((1, Some(0), Access::Exec)),
+        _ => None
+    }
+}
+
+// by the arch of the process, the others (and x32 on x86_64) are skipped with a warning once
+fn get_path_syscall(info: &SyscallInfo, skipped_archs: &mut HashSet<u32>) -> Option<(usize, Option<usize>, Access)> {
+    let nr = info.data[0] as i64;
+    let is_x32 = cfg!(target_arch = "x86_64") && nr & 0x40000000 != 0;
+    if info.arch == AUDIT_ARCH && !is_x32 {
+        return get_native_path_syscall(nr)
+    }
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    if info.arch == COMPAT_AUDIT_ARCH {
+        return get_compat_path_syscall(nr)
+    }
+    if skipped_archs.insert(info.arch) {
+        eprintln!("Skipping the syscalls of the processes with unsupported arch or ABI: {:#x}{}",
+            info.arch, if is_x32 { " (x32)" } else { "" })
+    }
+    None
+}
+
+fn classify(path: &Path, access: &Access) -> Option<&'static str> {
+    let path_str = path.to_string_lossy();
+    if SKIP_PREFIXES.iter().any(|prefix| path_str.starts_with(prefix)) ||
+        SKIP_FILES.contains(&path_str.as_ref()) || !path.is_file() {
+        return None
+    }
+    let is_elf = get_elf_info(&path_str).is_ok();
+    if *access == Access::Exec && (is_elf || is_script(&path.to_path_buf()).unwrap_or(false)) {
+        Some("bin")
+    } else if is_elf && path.file_name().unwrap_or_default().to_string_lossy().contains(".so") {
+        Some("lib")
+    } else if !is_elf && DATA_PREFIXES.iter().any(|prefix| path_str.starts_with(prefix)) {
+        Some("data")
+    } else {
+        None
+    }
+}
+
+pub fn trace(command: &[OsString], timeout: Option<Duration>) -> Result<Vec<String>> {
+    let exe = if command[0].as_bytes().contains(&b'/') {
+        PathBuf::from(&command[0])
+    } else {
+        which(&command[0]).ok_or_else(|| Error::new(NotFound, "not found in PATH"))?
+    };
+    let child = unsafe {
+        Command::new(&exe).args(&command[1..])
+            .pre_exec(|| ptrace::traceme().map_err(Error::from))
+            .spawn()?
+    };
+    let main_pid = Pid::from_raw(child.id() as i32);
+    // stopped by SIGTRAP after the exec
+    waitpid(main_pid, None)?;
+    ptrace::setoptions(main_pid, Options::PTRACE_O_TRACESYSGOOD | Options::PTRACE_O_TRACEFORK |
+        Options::PTRACE_O_TRACEVFORK | Options::PTRACE_O_TRACECLONE | Options::PTRACE_O_TRACEEXEC |
+        Options::PTRACE_O_EXITKILL)?;
+    ptrace::syscall(main_pid, None)?;
+    let tracees: Arc<Mutex<HashSet<Pid>>> = Arc::new(Mutex::new(HashSet::from([main_pid])));
+    // the forked processes too, they would run until the main one exits otherwise
+    if let Some(timeout) = timeout {
+        let tracees = Arc::clone(&tracees);
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            for pid in tracees.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+                unsafe { libc::kill(pid.as_raw(), libc::SIGKILL) };
+            }
+        });
+    }
+    let lock_tracees = || tracees.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let mut files: BTreeSet<(PathBuf, Access)> = BTreeSet::new();
+    files.insert((exe, Access::Exec));
+    let mut pending: HashMap<Pid, (PathBuf, Access)> = HashMap::new();
+    let mut mems: HashMap<Pid, File> = HashMap::new();
+    let mut skipped_archs: HashSet<u32> = HashSet::new();
+    let mut unsupported = None;
+    loop {
+        let status = match waitpid(None, Some(WaitPidFlag::__WALL)) {
+            Ok(status) => status,
+            Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => break,
+            Err(err) => return Err(err.into())
+        };
+        let (pid, signal) = match status {
+            WaitStatus::PtraceSyscall(pid) => {
+                match get_syscall_info(pid) {
+                    Ok(info) => match info.op {
+                        SYSCALL_INFO_ENTRY => {
+                            pending.remove(&pid);
+                            if let Some((path_arg, dirfd_arg, access)) = get_path_syscall(&info, &mut skipped_archs) {
+                                let mem = match mems.entry(pid) {
+                                    Entry::Occupied(entry) => Some(entry.into_mut()),
+                                    Entry::Vacant(entry) => File::open(format!("/proc/{pid}/mem")).ok()
+                                        .map(|mem| entry.insert(mem))
+                                };
+                                let path = mem.and_then(|mem| get_path_arg(pid, mem,
+                                    dirfd_arg.map(|arg| info.data[1 + arg]), info.data[1 + path_arg]));
+                                if let Some(path) = path {
+                                    pending.insert(pid, (path, access));
+                                }
+                            }
+                        }
+                        SYSCALL_INFO_EXIT => {
+                            if let Some((path, access)) = pending.remove(&pid) {
+                                if info.data[1] as u8 == 0 && (info.data[0] as i64) >= 0 {
+                                    if access == Access::Readlink {
+                                        if let Ok(target) = fs::canonicalize(&path) {
+                                            files.insert((target, Access::Open));
+                                        }
+                                        files.insert((path, Access::Open));
+                                    } else {
+                                        files.insert((path, access));
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    // PTRACE_GET_SYSCALL_INFO appeared in Linux 5.3
+                    Err(err) if matches!(err.raw_os_error(), Some(libc::EIO | libc::EINVAL)) => {
+                        unsupported = Some(Error::new(Unsupported,
+                            format!("PTRACE_GET_SYSCALL_INFO is not supported by the kernel ({err}), Linux 5.3 or newer is required")));
+                        break
+                    }
+                    // the process may have been killed meanwhile
+                    Err(_) => {}
+                }
+                (pid, None)
+            }
+            WaitStatus::PtraceEvent(pid, _, event) => {
+                // the exec replaces the memory, and after it there is no syscall exit stop of a failure
+                if event == Event::PTRACE_EVENT_EXEC as i32 {
+                    mems.remove(&pid);
+                    if let Some((path, Access::Exec)) = pending.remove(&pid) {
+                        files.insert((path, Access::Exec));
+                    }
+                }
+                (pid, None)
+            }
+            // the first stop of the new processes
+            WaitStatus::Stopped(pid, Signal::SIGSTOP) if lock_tracees().insert(pid) => (pid, None),
+            WaitStatus::Stopped(pid, signal) => (pid, Some(signal)),
+            WaitStatus::Exited(pid, _) | WaitStatus::Signaled(pid, _, _) => {
+                lock_tracees().remove(&pid);
+                pending.remove(&pid);
+                mems.remove(&pid);
+                if pid == main_pid {
+                    break
+                }
+                continue
+            }
+            _ => continue
+        };
+        lock_tracees().insert(pid);
+        // the process may have been killed meanwhile
+        let _ = ptrace::syscall(pid, signal);
+    }
+    // the processes it left are traced and stopped, they do not outlive the trace
+    for pid in lock_tracees().iter() {
+        unsafe { libc::kill(pid.as_raw(), libc::SIGKILL) };
+    }
+    if let Some(err) = unsupported {
+        return Err(err)
+    }
+
+    let mut list = Vec::new();
+    let mut seen = HashSet::new();
+    for (path, access) in &files {
+        if let Some(kind) = classify(path, access) {
+            if seen.insert(path) {
+                list.push(format!("{kind} {}", path.display()))
+            }
+        }
+    }
+    Ok(list)
+}
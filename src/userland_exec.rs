@@ -0,0 +1,417 @@
+// Maps the interpreter into the current process, builds a fresh stack with
+// argv, envp and auxv for it and jumps to its entry point, like the kernel does.
+// It replaces the userland-execve crate (since the AT_EXECFN change), whose exec(path, argv, envp) -> !
+// takes no AT_EXECFN, builds its own auxv from a few getauxval values instead of forwarding the
+// kernel one, and never returns an error, so sharun could not fall back to the kernel exec when
+// the mappings are denied. The stack layout and the mappings are covered by the tests below.
+
+use std::{
+    ptr,
+    convert::Infallible,
+    fs::{File, read},
+    path::Path,
+    ffi::{CStr, CString},
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    io::{Result, Error, ErrorKind::InvalidData}
+};
+
+use nix::libc;
+use crate::read_elf_headers;
+use goblin::elf::{
+    Elf,
+    header::ET_DYN,
+    program_header::{ProgramHeader, PT_LOAD, PT_PHDR, PF_R, PF_W, PF_X}
+};
+
+
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// used only if /proc/self/auxv is not available
+const AUXV_PASSTHROUGH: [libc::c_ulong; 14] = [
+    libc::AT_HWCAP, libc::AT_HWCAP2, libc::AT_PAGESZ, libc::AT_CLKTCK,
+    libc::AT_FLAGS, libc::AT_UID, libc::AT_EUID, libc::AT_GID, libc::AT_EGID,
+    libc::AT_SECURE, libc::AT_SYSINFO_EHDR, libc::AT_MINSIGSTKSZ,
+    libc::AT_PLATFORM, libc::AT_BASE_PLATFORM
+];
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn page_down(addr: usize) -> usize {
+    addr & !(page_size() - 1)
+}
+
+fn page_up(addr: usize) -> usize {
+    page_down(addr + page_size() - 1)
+}
+
+fn get_prot(header: &ProgramHeader) -> libc::c_int {
+    let mut prot = libc::PROT_NONE;
+    if header.p_flags & PF_R != 0 { prot |= libc::PROT_READ }
+    if header.p_flags & PF_W != 0 { prot |= libc::PROT_WRITE }
+    if header.p_flags & PF_X != 0 { prot |= libc::PROT_EXEC }
+    prot
+}
+
+unsafe fn map(addr: usize, len: usize, prot: libc::c_int, flags: libc::c_int, fd: libc::c_int, offset: usize) -> Result<usize> {
+    let addr = libc::mmap(addr as *mut libc::c_void, len, prot, flags, fd, offset as libc::off_t);
+    if addr == libc::MAP_FAILED {
+        return Err(Error::last_os_error())
+    }
+    Ok(addr as usize)
+}
+
+struct LoadedElf {
+    entry: usize,
+    phdr: usize,
+    phent: usize,
+    phnum: usize,
+    reserve: usize,
+    size: usize
+}
+
+// fails the same way as the real mappings would, SELinux execmem/execmod, PaX MPROTECT,
+// noexec mounts and seccomp, but before the address space is touched
+fn probe(fd: libc::c_int) -> Result<()> {
+    let len = page_size();
+    unsafe {
+        let file_page = map(0, len, libc::PROT_READ | libc::PROT_EXEC, libc::MAP_PRIVATE, fd, 0)?;
+        libc::munmap(file_page as *mut libc::c_void, len);
+        let anon_page = map(0, len, libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)?;
+        let protected = libc::mprotect(anon_page as *mut libc::c_void, len, libc::PROT_READ | libc::PROT_EXEC);
+        let err = Error::last_os_error();
+        libc::munmap(anon_page as *mut libc::c_void, len);
+        if protected != 0 {
+            return Err(err)
+        }
+    }
+    Ok(())
+}
+
+// the segments are mapped from the file, only the headers are read
+fn load_elf(path: &Path) -> Result<LoadedElf> {
+    let mut file = File::open(path)?;
+    let (_, _, program_headers) = read_elf_headers(&mut file)?;
+    let mut header_bytes = [0; 64];
+    file.read_exact_at(&mut header_bytes, 0)?;
+    let header = Elf::parse_header(&header_bytes).map_err(|err| Error::new(InvalidData, err))?;
+    let loads: Vec<&ProgramHeader> = program_headers.iter()
+        .filter(|header| header.p_type == PT_LOAD).collect();
+    if loads.is_empty() {
+        return Err(Error::new(InvalidData, "no PT_LOAD segments"))
+    }
+    let min_vaddr = page_down(loads.iter().map(|header| header.p_vaddr as usize).min().unwrap_or_default());
+    let max_vaddr = page_up(loads.iter().map(|header| (header.p_vaddr + header.p_memsz) as usize).max().unwrap_or_default());
+    let phdr_vaddr = if let Some(header) = program_headers.iter().find(|header| header.p_type == PT_PHDR) {
+        header.p_vaddr as usize
+    } else {
+        let phoff = header.e_phoff;
+        let header = loads.iter()
+            .find(|header| header.p_offset <= phoff && phoff < header.p_offset + header.p_filesz)
+            .ok_or_else(|| Error::new(InvalidData, "program headers are not loaded"))?;
+        (header.p_vaddr + phoff - header.p_offset) as usize
+    };
+
+    let fd = file.as_raw_fd();
+    probe(fd)?;
+    unsafe {
+        let (reserve_addr, reserve_flags) = if header.e_type == ET_DYN {
+            (0, 0)
+        } else {
+            (min_vaddr, libc::MAP_FIXED_NOREPLACE)
+        };
+        let size = max_vaddr - min_vaddr;
+        let reserve = map(reserve_addr, size, libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | reserve_flags, -1, 0)?;
+        let base = reserve - min_vaddr;
+        if let Err(err) = map_segments(&loads, fd, base) {
+            // the segments are all inside of the reservation
+            libc::munmap(reserve as *mut libc::c_void, size);
+            return Err(err)
+        }
+        Ok(LoadedElf {
+            entry: base + header.e_entry as usize,
+            phdr: base + phdr_vaddr,
+            phent: header.e_phentsize as usize,
+            phnum: header.e_phnum as usize,
+            reserve,
+            size
+        })
+    }
+}
+
+unsafe fn map_segments(loads: &[&ProgramHeader], fd: libc::c_int, base: usize) -> Result<()> {
+    for header in loads {
+        let prot = get_prot(header);
+        let seg_start = base + header.p_vaddr as usize;
+        let map_start = page_down(seg_start);
+        let file_end = seg_start + header.p_filesz as usize;
+        let mem_end = page_up(seg_start + header.p_memsz as usize);
+        let has_bss = header.p_memsz > header.p_filesz;
+        if header.p_filesz > 0 {
+            let map_prot = if has_bss { prot | libc::PROT_WRITE } else { prot };
+            map(map_start, file_end - map_start, map_prot, libc::MAP_PRIVATE | libc::MAP_FIXED,
+                fd, header.p_offset as usize - (seg_start - map_start))?;
+            if has_bss {
+                ptr::write_bytes(file_end as *mut u8, 0, page_up(file_end) - file_end);
+                if map_prot != prot &&
+                    libc::mprotect(map_start as *mut libc::c_void, page_up(file_end) - map_start, prot) != 0 {
+                    return Err(Error::last_os_error())
+                }
+            }
+        }
+        let bss_start = if header.p_filesz > 0 { page_up(file_end) } else { map_start };
+        if mem_end > bss_start {
+            map(bss_start, mem_end - bss_start, prot,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED, -1, 0)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_auxv() -> Result<Vec<(libc::c_ulong, usize)>> {
+    let auxv_bytes = read("/proc/self/auxv")?;
+    let word = size_of::<usize>();
+    let to_usize = |bytes: &[u8]| usize::from_ne_bytes(bytes.try_into().unwrap_or_default());
+    Ok(auxv_bytes.chunks_exact(word * 2)
+        .map(|entry| (to_usize(&entry[..word]) as libc::c_ulong, to_usize(&entry[word..])))
+        .take_while(|(key, _)| *key != libc::AT_NULL)
+        .collect())
+}
+
+fn get_auxv(elf: &LoadedElf, execfn: usize, random: usize) -> Vec<(libc::c_ulong, usize)> {
+    // keep everything the kernel gave us (hwcaps, rseq, vDSO, ...) in its order
+    let mut auxv = read_auxv().unwrap_or_else(|_| AUXV_PASSTHROUGH.iter()
+        .map(|&key| (key, unsafe { libc::getauxval(key) } as usize))
+        .filter(|&(key, val)| val != 0 ||
+            [libc::AT_UID, libc::AT_EUID, libc::AT_GID, libc::AT_EGID, libc::AT_SECURE].contains(&key))
+        .collect());
+    // AT_EXECFD belongs to a binfmt_misc 'O' flag handler of sharun itself
+    auxv.retain(|&(key, _)| key != libc::AT_EXECFD);
+    for (key, val) in [
+        (libc::AT_PHDR, elf.phdr),
+        (libc::AT_PHENT, elf.phent),
+        (libc::AT_PHNUM, elf.phnum),
+        (libc::AT_BASE, 0),
+        (libc::AT_ENTRY, elf.entry),
+        (libc::AT_EXECFN, execfn),
+        (libc::AT_RANDOM, random)
+    ] {
+        match auxv.iter_mut().find(|(auxv_key, _)| *auxv_key == key) {
+            Some(entry) => entry.1 = val,
+            None => auxv.push((key, val))
+        }
+    }
+    auxv
+}
+
+struct Stack {
+    top: usize,
+    bottom: usize
+}
+
+impl Stack {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        if self.top - self.bottom < bytes.len() {
+            return Err(Error::new(InvalidData, "arguments and environment are too large"))
+        }
+        self.top -= bytes.len();
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), self.top as *mut u8, bytes.len()) }
+        Ok(self.top)
+    }
+}
+
+fn build_stack(elf: &LoadedElf, argv: &[CString], envp: &[CString], execfn: &CStr) -> Result<usize> {
+    let bottom = unsafe {
+        map(0, STACK_SIZE, libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK, -1, 0)?
+    };
+    let mut stack = Stack { top: bottom + STACK_SIZE, bottom };
+    fill_stack(&mut stack, elf, argv, envp, execfn).inspect_err(|_| unsafe {
+        libc::munmap(bottom as *mut libc::c_void, STACK_SIZE);
+    })
+}
+
+fn fill_stack(stack: &mut Stack, elf: &LoadedElf, argv: &[CString], envp: &[CString], execfn: &CStr) -> Result<usize> {
+    let execfn = stack.push_bytes(execfn.to_bytes_with_nul())?;
+    let mut random = [0u8; 16];
+    unsafe { libc::getrandom(random.as_mut_ptr() as *mut libc::c_void, random.len(), 0) };
+    let random = stack.push_bytes(&random)?;
+    let mut strings = |strings: &[CString]| -> Result<Vec<usize>> {
+        strings.iter().rev().map(|string| stack.push_bytes(string.to_bytes_with_nul()))
+            .collect::<Result<Vec<usize>>>().map(|mut ptrs| { ptrs.reverse(); ptrs })
+    };
+    let envp_ptrs = strings(envp)?;
+    let argv_ptrs = strings(argv)?;
+
+    let mut auxv = get_auxv(elf, execfn, random);
+    for (key, val) in auxv.iter_mut() {
+        if [libc::AT_PLATFORM, libc::AT_BASE_PLATFORM].contains(key) {
+            let platform = unsafe { CStr::from_ptr(*val as *const libc::c_char) };
+            *val = stack.push_bytes(platform.to_bytes_with_nul())?
+        }
+    }
+
+    let mut words = vec![argv_ptrs.len()];
+    words.extend(&argv_ptrs);
+    words.push(0);
+    words.extend(&envp_ptrs);
+    words.push(0);
+    for (key, val) in auxv {
+        words.extend([key as usize, val]);
+    }
+    words.extend([libc::AT_NULL as usize, 0]);
+
+    let words_len = words.len() * size_of::<usize>();
+    stack.top = (stack.top - words_len) & !15;
+    if stack.top < stack.bottom {
+        return Err(Error::new(InvalidData, "arguments and environment are too large"))
+    }
+    unsafe { ptr::copy_nonoverlapping(words.as_ptr(), stack.top as *mut usize, words.len()) }
+    Ok(stack.top)
+}
+
+fn reset_signals() {
+    // the process keeps our signal dispositions and alternate stack, the kernel would reset them
+    unsafe {
+        for signal in 1..libc::SIGRTMIN() {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, ptr::null(), &mut action) == 0 &&
+                action.sa_sigaction != libc::SIG_DFL && action.sa_sigaction != libc::SIG_IGN {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+        let altstack = libc::stack_t {
+            ss_sp: ptr::null_mut(),
+            ss_flags: libc::SS_DISABLE,
+            ss_size: 0
+        };
+        libc::sigaltstack(&altstack, ptr::null_mut());
+    }
+}
+
+unsafe fn jump(entry: usize, stack: usize) -> ! {
+    #[cfg(target_arch = "x86_64")]
+    std::arch::asm!(
+        "mov rsp, rdi",
+        "xor edx, edx",
+        "xor ebp, ebp",
+        "jmp rsi",
+        in("rdi") stack,
+        in("rsi") entry,
+        options(noreturn)
+    );
+    #[cfg(target_arch = "aarch64")]
+    std::arch::asm!(
+        "mov sp, x1",
+        "mov x0, xzr",
+        "mov x29, xzr",
+        "mov x30, xzr",
+        "br x2",
+        in("x1") stack,
+        in("x2") entry,
+        options(noreturn)
+    );
+}
+
+pub fn exec(path: &Path, argv: &[CString], envp: &[CString], execfn: &CStr) -> Result<Infallible> {
+    // nothing is left mapped on an error, so the caller can still use the kernel exec
+    let elf = load_elf(path)?;
+    let stack = build_stack(&elf, argv, envp, execfn).inspect_err(|_| unsafe {
+        libc::munmap(elf.reserve as *mut libc::c_void, elf.size);
+    })?;
+    reset_signals();
+    unsafe { jump(elf.entry, stack) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_elf_info;
+
+    fn read_cstr(addr: usize) -> &'static [u8] {
+        unsafe { CStr::from_ptr(addr as *const libc::c_char).to_bytes() }
+    }
+
+    #[test]
+    fn load_interpreter() {
+        let interp = get_elf_info("/proc/self/exe").unwrap().interp.unwrap();
+        let elf = load_elf(Path::new(&interp)).unwrap();
+        assert!(elf.reserve <= elf.entry && elf.entry < elf.reserve + elf.size);
+
+        let mut file = File::open(&interp).unwrap();
+        let (_, _, program_headers) = read_elf_headers(&mut file).unwrap();
+        assert_eq!(elf.phnum, program_headers.len());
+        let mut header_bytes = [0; 64];
+        file.read_exact_at(&mut header_bytes, 0).unwrap();
+        let header = Elf::parse_header(&header_bytes).unwrap();
+        let mut phdr_bytes = vec![0; elf.phnum * elf.phent];
+        file.read_exact_at(&mut phdr_bytes, header.e_phoff).unwrap();
+        let mapped = unsafe { std::slice::from_raw_parts(elf.phdr as *const u8, phdr_bytes.len()) };
+        assert_eq!(mapped, phdr_bytes);
+        unsafe { libc::munmap(elf.reserve as *mut libc::c_void, elf.size) };
+    }
+
+    #[test]
+    fn stack_layout() {
+        let elf = LoadedElf { entry: 0x1000, phdr: 0x40, phent: 56, phnum: 7, reserve: 0, size: 0 };
+        let argv = [c"ld.so".to_owned(), c"/bundle/shared/bin/app".to_owned()];
+        let envp = [c"A=1".to_owned(), c"B=2".to_owned()];
+        let top = build_stack(&elf, &argv, &envp, c"/bundle/shared/bin/app").unwrap();
+        assert_eq!(top % 16, 0);
+
+        let words = top as *const usize;
+        let word = |idx: usize| unsafe { *words.add(idx) };
+        assert_eq!(word(0), 2);
+        assert_eq!(read_cstr(word(1)), b"ld.so");
+        assert_eq!(read_cstr(word(2)), b"/bundle/shared/bin/app");
+        assert_eq!(word(3), 0);
+        assert_eq!(read_cstr(word(4)), b"A=1");
+        assert_eq!(read_cstr(word(5)), b"B=2");
+        assert_eq!(word(6), 0);
+
+        let mut auxv = Vec::new();
+        let mut idx = 7;
+        while word(idx) != libc::AT_NULL as usize {
+            auxv.push((word(idx) as libc::c_ulong, word(idx + 1)));
+            idx += 2
+        }
+        let get = |key| auxv.iter().find(|(auxv_key, _)| *auxv_key == key).map(|(_, val)| *val);
+        assert_eq!(get(libc::AT_ENTRY), Some(0x1000));
+        assert_eq!(get(libc::AT_PHDR), Some(0x40));
+        assert_eq!(get(libc::AT_PHENT), Some(56));
+        assert_eq!(get(libc::AT_PHNUM), Some(7));
+        assert_eq!(get(libc::AT_BASE), Some(0));
+        assert_eq!(read_cstr(get(libc::AT_EXECFN).unwrap()), b"/bundle/shared/bin/app");
+        let random = get(libc::AT_RANDOM).unwrap();
+        assert!(top < random && random < top + STACK_SIZE);
+
+        // the rest is what the kernel gave to this process
+        for (key, val) in read_auxv().unwrap() {
+            match key {
+                libc::AT_PHDR | libc::AT_PHENT | libc::AT_PHNUM | libc::AT_BASE | libc::AT_ENTRY |
+                libc::AT_RANDOM | libc::AT_EXECFD | libc::AT_EXECFN => {}
+                libc::AT_PLATFORM | libc::AT_BASE_PLATFORM =>
+                    assert_eq!(read_cstr(get(key).unwrap()), read_cstr(val)),
+                _ => assert_eq!(get(key), Some(val), "auxv entry {key}")
+            }
+        }
+    }
+
+    #[test]
+    fn too_large_stack() {
+        let elf = LoadedElf { entry: 0, phdr: 0, phent: 0, phnum: 0, reserve: 0, size: 0 };
+        let env = CString::new(format!("A={}", "a".repeat(STACK_SIZE))).unwrap();
+        let err = build_stack(&elf, &[], &[env], c"app").unwrap_err();
+        assert_eq!(err.kind(), InvalidData);
+    }
+
+    #[test]
+    fn probe_mappings() {
+        let file = File::open("/proc/self/exe").unwrap();
+        probe(file.as_raw_fd()).unwrap();
+    }
+}